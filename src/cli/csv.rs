@@ -1,29 +1,457 @@
-use crate::{process_csv, verify_path, CmdExecutor};
-use clap::Parser;
-use std::str::FromStr;
+use super::{verify_file, verify_path};
+use crate::{
+    csv_stats, dedup_csv, diff_csv, get_content, get_reader, get_writer, inspect_csv, join_csv,
+    mask_csv, merge_csv, process_csv, process_to_csv, sort_csv, split_csv, validate_csv,
+    write_diff, write_report, Aggregate, Blake3, CmdExecutor, ColumnMask, CsvConvertOptions,
+    CsvReadOptions, CsvSchema, CsvTable, DiffStyle, InputEncoding, JoinKey, JoinKind, SortOptions,
+    SplitBy, SqlQuery,
+};
+use clap::{ArgAction, Args, Parser};
+use enum_dispatch::enum_dispatch;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+// rcli csv -i input.csv -o output.json --format json
+// cat input.csv | rcli csv -o output.yaml
+// rcli csv from-json -i input.json -o output.csv
+// rcli csv sort -i input.csv --by Age --numeric
+// rcli csv stats -i input.csv --group-by Team --agg sum:Salary,avg:Age,count
+// rcli csv inspect -i input.csv
+// rcli csv join players.csv contracts.csv --on id=player_id --how left
+// rcli csv diff old.csv new.csv --key id --style unified
+// rcli csv query "SELECT Name FROM juventus WHERE \"Kit Number\" < 10" assets/juventus.csv
+// rcli csv validate -i input.csv --schema schema.json
+
+#[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct CsvCommand {
+    #[command(subcommand)]
+    pub cmd: Option<CsvSubcommand>,
+
+    #[command(flatten)]
+    pub opts: Option<CsvOpts>,
+}
+
+#[derive(Debug, Parser)]
+#[enum_dispatch(CmdExecutor)]
+pub enum CsvSubcommand {
+    #[command(
+        name = "from-json",
+        visible_alias = "from-ndjson",
+        about = "Convert a JSON array or NDJSON stream of objects to CSV"
+    )]
+    FromJson(FromJsonOpts),
+    #[command(
+        name = "from-yaml",
+        about = "Convert a YAML sequence of objects to CSV"
+    )]
+    FromYaml(FromYamlOpts),
+    #[command(about = "Sort rows by one or more columns, spilling to disk for large files")]
+    Sort(SortOpts),
+    #[command(about = "Drop rows with a duplicate key, keeping the first one")]
+    Dedup(DedupOpts),
+    #[command(about = "Aggregate columns, optionally grouped by other columns")]
+    Stats(StatsOpts),
+    #[command(
+        about = "Show column types, empty cells, distinct counts, min/max and malformed rows"
+    )]
+    Inspect(InspectOpts),
+    #[command(
+        about = "Check rows against a schema, exiting with an error if any row doesn't match"
+    )]
+    Validate(ValidateOpts),
+    #[command(about = "Join two csv files on one or more key columns")]
+    Join(JoinOpts),
+    #[command(about = "Run a SQL SELECT over csv files, each one a table named after the file")]
+    Query(QueryOpts),
+    #[command(about = "Show rows added, removed or changed between two versions of a csv file")]
+    Diff(DiffOpts),
+    #[command(about = "Hash, redact, truncate or fake columns before sharing a csv file")]
+    Mask(MaskOpts),
+    #[command(about = "Split a csv file into files of N rows or one file per column value")]
+    Split(SplitOpts),
+    #[command(about = "Concatenate csv files with the same columns, in any order")]
+    Merge(MergeOpts),
+}
+
+/// Csv input shared by the subcommands
+#[derive(Debug, Args)]
+pub struct CsvInputArgs {
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+
+    #[command(flatten)]
+    pub read: CsvReadArgs,
+}
+
+/// How csv inputs are read, also for subcommands taking several files
+#[derive(Debug, Args)]
+pub struct CsvReadArgs {
+    /// Field delimiter, e.g. `;`, `|`, `\t`, `tab` or a multi-byte sequence like `::`
+    #[arg(short, long, value_parser = parse_delimiter, default_value = ",")]
+    pub delimiter: String,
+
+    /// Treat the first row as data instead of a header
+    #[arg(long = "no-header", action = ArgAction::SetFalse)]
+    pub header: bool,
+
+    /// Input encoding, e.g. utf-8, utf-16le, gbk or latin1; `auto` looks for a BOM
+    /// and guesses legacy encodings from the content. Defaults to auto, except
+    /// for inspect and validate which default to utf-8 to report invalid bytes
+    #[arg(long, value_parser = parse_encoding)]
+    pub encoding: Option<InputEncoding>,
+}
+
+impl CsvReadArgs {
+    pub fn read_options(&self) -> CsvReadOptions {
+        self.read_options_with(InputEncoding::Auto)
+    }
+
+    // `encoding` applies when none was given
+    pub fn read_options_with(&self, encoding: InputEncoding) -> CsvReadOptions {
+        CsvReadOptions {
+            delimiter: self.delimiter.clone(),
+            has_headers: self.header,
+            encoding: self.encoding.unwrap_or(encoding),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct SortOpts {
+    #[command(flatten)]
+    pub input: CsvInputArgs,
+
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    /// Columns to sort by, in order of precedence
+    #[arg(long, value_delimiter = ',', required = true)]
+    pub by: Vec<String>,
+
+    #[arg(long)]
+    pub desc: bool,
+
+    /// Compare values as numbers, non-numeric values sort last
+    #[arg(long)]
+    pub numeric: bool,
+
+    /// Rows kept in memory before a sorted run is written to a temporary file
+    #[arg(long, default_value_t = 100_000)]
+    pub buffer_rows: usize,
+}
+
+#[derive(Debug, Parser)]
+pub struct DedupOpts {
+    #[command(flatten)]
+    pub input: CsvInputArgs,
+
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    /// Columns forming the key, the whole row is used when omitted
+    #[arg(long, value_delimiter = ',')]
+    pub key: Vec<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct MaskOpts {
+    #[command(flatten)]
+    pub input: CsvInputArgs,
+
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    /// Mask for a column as `column:mask`, where mask is hash, redact, truncate:N,
+    /// year-only or fake, e.g. `--column Name:hash --column DOB:year-only`
+    #[arg(long = "column", required = true)]
+    pub columns: Vec<ColumnMask>,
+
+    /// Blake3 key file for the hash and fake masks, see `rcli text keygen`
+    #[arg(short, long, value_parser = verify_file)]
+    pub key: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct SplitOpts {
+    #[command(flatten)]
+    pub input: CsvInputArgs,
+
+    /// Records per output file
+    #[arg(
+        long,
+        conflicts_with = "by_column",
+        required_unless_present = "by_column"
+    )]
+    pub rows: Option<usize>,
+
+    /// Write one file per distinct value of this column
+    #[arg(long)]
+    pub by_column: Option<String>,
+
+    /// Directory the files are written to
+    #[arg(long, value_parser = verify_path, default_value = ".")]
+    pub output_dir: PathBuf,
+
+    /// File name prefix, defaults to the input file name without extension
+    #[arg(long)]
+    pub prefix: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct MergeOpts {
+    #[arg(value_parser = verify_file, required = true)]
+    pub inputs: Vec<String>,
+
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    /// How every input is read
+    #[command(flatten)]
+    pub read: CsvReadArgs,
+}
+
+#[derive(Debug, Parser)]
+pub struct StatsOpts {
+    #[command(flatten)]
+    pub input: CsvInputArgs,
+
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    #[arg(short, long, value_parser = parse_format, default_value = "table")]
+    pub format: OutputFormat,
+
+    #[arg(long, value_delimiter = ',')]
+    pub group_by: Vec<String>,
+
+    /// Aggregates such as `count`, `count:Col`, `sum:Col`, `avg:Col`, `min:Col`, `max:Col`
+    #[arg(long, value_delimiter = ',', default_value = "count")]
+    pub agg: Vec<Aggregate>,
+}
+
+#[derive(Debug, Parser)]
+pub struct InspectOpts {
+    #[command(flatten)]
+    pub input: CsvInputArgs,
+
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    #[arg(short, long, value_parser = parse_format, default_value = "table")]
+    pub format: OutputFormat,
+}
+
+#[derive(Debug, Parser)]
+pub struct ValidateOpts {
+    #[command(flatten)]
+    pub input: CsvInputArgs,
+
+    /// JSON or YAML file with column types, e.g. `{"Age": {"type": "integer", "required": true}}`
+    #[arg(long, value_parser = verify_file)]
+    pub schema: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct JoinOpts {
+    #[arg(value_parser = verify_file)]
+    pub left: String,
+
+    #[arg(value_parser = verify_file)]
+    pub right: String,
+
+    /// Key columns, `id` or `left_name=right_name` when they differ
+    #[arg(long, value_delimiter = ',', required = true)]
+    pub on: Vec<JoinKey>,
+
+    /// Join type: inner, left or outer
+    #[arg(long, default_value = "inner")]
+    pub how: JoinKind,
+
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    /// Output format, guessed from the output file extension when omitted,
+    /// falling back to csv
+    #[arg(short, long, value_parser = parse_format)]
+    pub format: Option<OutputFormat>,
+
+    /// How both inputs are read
+    #[command(flatten)]
+    pub read: CsvReadArgs,
+}
+
+#[derive(Debug, Parser)]
+pub struct QueryOpts {
+    /// SELECT statement, quote column names with spaces like "Kit Number"
+    pub sql: String,
+
+    /// Csv files used as tables, `<table>.csv` in the current directory is
+    /// tried for tables without a matching file
+    #[arg(value_parser = verify_file)]
+    pub files: Vec<String>,
+
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    /// Output format, guessed from the output file extension when omitted,
+    /// falling back to table
+    #[arg(short, long, value_parser = parse_format)]
+    pub format: Option<OutputFormat>,
+
+    /// How every table is read
+    #[command(flatten)]
+    pub read: CsvReadArgs,
+}
+
+#[derive(Debug, Parser)]
+pub struct DiffOpts {
+    #[arg(value_parser = verify_file)]
+    pub old: String,
+
+    #[arg(value_parser = verify_file)]
+    pub new: String,
+
+    /// Columns identifying a row in both files
+    #[arg(long, value_delimiter = ',', required = true)]
+    pub key: Vec<String>,
+
+    /// Report style: human, json or unified
+    #[arg(long, default_value = "human")]
+    pub style: DiffStyle,
+
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    /// How both files are read
+    #[command(flatten)]
+    pub read: CsvReadArgs,
+}
+
+#[derive(Debug, Parser)]
+pub struct FromJsonOpts {
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+
+    #[arg(short, long, default_value = "output.csv")]
+    pub output: String,
+
+    #[arg(short, long, value_parser = parse_delimiter, default_value = ",")]
+    pub delimiter: String,
+
+    /// Write arrays as indexed columns like `tags[0]` instead of JSON text
+    #[arg(long)]
+    pub flatten_arrays: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct FromYamlOpts {
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+
+    #[arg(short, long, default_value = "output.csv")]
+    pub output: String,
+
+    #[arg(short, long, value_parser = parse_delimiter, default_value = ",")]
+    pub delimiter: String,
+
+    /// Write arrays as indexed columns like `tags[0]` instead of JSON text
+    #[arg(long)]
+    pub flatten_arrays: bool,
+}
 
 #[derive(Debug, Parser)]
 pub struct CsvOpts {
-    #[arg(short, long, value_parser = verify_path)]
+    /// Input file, `-` reads from stdin; .xlsx, .xls and .ods workbooks are read as well
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
     pub input: String,
 
+    /// Sheet of a workbook input, by name or zero-based index, defaults to the first one
+    #[arg(long)]
+    pub sheet: Option<String>,
+
+    /// Output file, `-` writes to stdout
     #[arg(short, long, default_value = "output.json")]
     pub output: String,
 
-    #[arg(short, long, default_value = ",")]
+    /// Field delimiter, e.g. `;`, `|`, `\t`, `tab` or a multi-byte sequence like `::`
+    #[arg(short, long, value_parser = parse_delimiter, default_value = ",")]
     pub delimiter: String,
 
-    #[arg(short, long, value_parser = parse_format, default_value = "csv")]
-    pub format: OutputFormat,
+    /// Output format: json, yaml, toml, ndjson (jsonl), csv, table or parquet; guessed
+    /// from the output file extension when omitted, falling back to json
+    #[arg(short, long, value_parser = parse_format)]
+    pub format: Option<OutputFormat>,
+
+    /// Field delimiter of csv output, must be a single byte
+    #[arg(long, value_parser = parse_delimiter, default_value = ",")]
+    pub output_delimiter: String,
 
-    #[arg(long, default_value_t = true)]
+    /// Treat the first row as data instead of a header
+    #[arg(long = "no-header", action = ArgAction::SetFalse)]
     pub header: bool,
+
+    /// Column names to use instead of the header, e.g. `--columns a,b,c`
+    #[arg(long, value_delimiter = ',')]
+    pub columns: Option<Vec<String>>,
+
+    /// Convert integers, floats, booleans and empty cells into typed values
+    #[arg(long)]
+    pub infer: bool,
+
+    /// JSON or YAML file pinning column types, e.g. `{"Kit Number": "integer"}`
+    #[arg(long, value_parser = verify_file)]
+    pub schema: Option<String>,
+
+    /// Columns to keep by name or zero-based index, e.g. `--select Name,3`
+    #[arg(long, value_delimiter = ',')]
+    pub select: Option<Vec<String>>,
+
+    /// Rename columns, e.g. `--rename "Kit Number=number"`
+    #[arg(long, value_parser = parse_rename, value_delimiter = ',')]
+    pub rename: Vec<(String, String)>,
+
+    /// Only keep rows matching the expression, e.g. `--where "Nationality == Italy"`
+    #[arg(long = "where")]
+    pub filters: Vec<String>,
+
+    /// Build nested objects and arrays from column names like `address.city` and `tags[0]`
+    #[arg(long)]
+    pub unflatten: bool,
+
+    /// Input encoding, e.g. utf-8, utf-16le, gbk or latin1; `auto` looks for a BOM
+    /// and guesses legacy encodings from the content
+    #[arg(long, value_parser = parse_encoding, default_value = "auto")]
+    pub encoding: InputEncoding,
+
+    /// Print the input encoding to stderr
+    #[arg(short, long)]
+    pub verbose: bool,
+
+    /// Threads converting records, 0 uses every core; output stays in input order
+    #[arg(long, default_value_t = 1)]
+    pub threads: usize,
 }
 
 #[derive(Debug, Clone, Copy)]
 pub enum OutputFormat {
     Json,
     Yaml,
+    Toml,
+    Ndjson,
+    Csv,
+    Table,
+    Parquet,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum InputFormat {
+    Json,
+    Yaml,
 }
 
 impl From<OutputFormat> for &'static str {
@@ -31,6 +459,28 @@ impl From<OutputFormat> for &'static str {
         match value {
             OutputFormat::Json => "json",
             OutputFormat::Yaml => "yaml",
+            OutputFormat::Toml => "toml",
+            OutputFormat::Ndjson => "ndjson",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Table => "table",
+            OutputFormat::Parquet => "parquet",
+        }
+    }
+}
+
+impl OutputFormat {
+    // an explicit --format wins over the extension of the output file
+    pub fn resolve(format: Option<Self>, output: &str, fallback: Self) -> Self {
+        format
+            .or_else(|| Self::from_extension(output))
+            .unwrap_or(fallback)
+    }
+
+    pub fn from_extension(path: &str) -> Option<Self> {
+        let ext = Path::new(path).extension()?.to_str()?;
+        match ext.to_lowercase().as_str() {
+            "txt" => Some(OutputFormat::Table),
+            ext => ext.parse().ok(),
         }
     }
 }
@@ -39,21 +489,493 @@ fn parse_format(format: &str) -> Result<OutputFormat, anyhow::Error> {
     format.to_lowercase().parse()
 }
 
+fn parse_delimiter(delimiter: &str) -> Result<String, anyhow::Error> {
+    let ret = match delimiter {
+        "tab" => "\t".to_string(),
+        "comma" => ",".to_string(),
+        "semicolon" => ";".to_string(),
+        "pipe" => "|".to_string(),
+        "space" => " ".to_string(),
+        _ => unescape(delimiter)?,
+    };
+    match ret.as_str() {
+        "" => Err(anyhow::anyhow!("Delimiter can't be empty")),
+        s if s.contains(['"', '\n', '\r']) => Err(anyhow::anyhow!(
+            "Delimiter can't contain quotes or line breaks"
+        )),
+        _ => Ok(ret),
+    }
+}
+
+// resolve backslash escapes such as `\t`, `\\` and `\x1f`
+fn unescape(s: &str) -> Result<String, anyhow::Error> {
+    let mut ret = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            ret.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => ret.push('\t'),
+            Some('\\') => ret.push('\\'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let byte = u8::from_str_radix(&hex, 16)
+                    .map_err(|_| anyhow::anyhow!("Invalid escape: \\x{}", hex))?;
+                ret.push(char::from(byte));
+            }
+            Some(c) => return Err(anyhow::anyhow!("Invalid escape: \\{}", c)),
+            None => return Err(anyhow::anyhow!("Dangling escape")),
+        }
+    }
+    Ok(ret)
+}
+
+fn parse_encoding(encoding: &str) -> Result<InputEncoding, anyhow::Error> {
+    encoding.parse()
+}
+
+fn parse_rename(rename: &str) -> Result<(String, String), anyhow::Error> {
+    match rename.split_once('=') {
+        Some((from, to)) if !from.is_empty() && !to.is_empty() => Ok((from.into(), to.into())),
+        _ => Err(anyhow::anyhow!("Expected old=new, got {:?}", rename)),
+    }
+}
+
+fn single_byte(delimiter: &str) -> Result<u8, anyhow::Error> {
+    match delimiter.as_bytes() {
+        [b] => Ok(*b),
+        _ => Err(anyhow::anyhow!(
+            "Delimiter must be a single byte, got {:?}",
+            delimiter
+        )),
+    }
+}
+
 impl FromStr for OutputFormat {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "json" => Ok(OutputFormat::Json),
-            "yaml" => Ok(OutputFormat::Yaml),
+            "yaml" | "yml" => Ok(OutputFormat::Yaml),
+            "toml" => Ok(OutputFormat::Toml),
+            "ndjson" | "jsonl" | "jsonlines" => Ok(OutputFormat::Ndjson),
+            "csv" => Ok(OutputFormat::Csv),
+            "table" => Ok(OutputFormat::Table),
+            "parquet" => Ok(OutputFormat::Parquet),
             _ => Err(anyhow::anyhow!("Invalid format")),
         }
     }
 }
 
+impl CmdExecutor for CsvCommand {
+    async fn execute(self) -> anyhow::Result<()> {
+        match (self.cmd, self.opts) {
+            (Some(cmd), _) => cmd.execute().await,
+            (None, Some(opts)) => opts.execute().await,
+            (None, None) => Err(anyhow::anyhow!("Missing csv options")),
+        }
+    }
+}
+
+impl CmdExecutor for FromJsonOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let delimiter = single_byte(&self.delimiter)?;
+        process_to_csv(
+            &self.input,
+            &self.output,
+            InputFormat::Json,
+            delimiter,
+            self.flatten_arrays,
+        )
+    }
+}
+
+impl CmdExecutor for FromYamlOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let delimiter = single_byte(&self.delimiter)?;
+        process_to_csv(
+            &self.input,
+            &self.output,
+            InputFormat::Yaml,
+            delimiter,
+            self.flatten_arrays,
+        )
+    }
+}
+
+impl CmdExecutor for SortOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let opts = SortOptions {
+            by: self.by,
+            descending: self.desc,
+            numeric: self.numeric,
+            buffer_rows: self.buffer_rows,
+        };
+        let reader = get_reader(&self.input.input)?;
+        let writer = get_writer(&self.output)?;
+        sort_csv(reader, writer, &self.input.read.read_options(), &opts)?;
+        Ok(())
+    }
+}
+
+impl CmdExecutor for DedupOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let reader = get_reader(&self.input.input)?;
+        let writer = get_writer(&self.output)?;
+        dedup_csv(reader, writer, &self.input.read.read_options(), &self.key)?;
+        Ok(())
+    }
+}
+
+impl CmdExecutor for MaskOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let key = self
+            .key
+            .as_deref()
+            .map(|path| Blake3::try_new(get_content(path)?))
+            .transpose()?;
+        let reader = get_reader(&self.input.input)?;
+        let writer = get_writer(&self.output)?;
+        mask_csv(
+            reader,
+            writer,
+            &self.input.read.read_options(),
+            &self.columns,
+            key.as_ref(),
+        )?;
+        Ok(())
+    }
+}
+
+impl CmdExecutor for SplitOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let by = match (self.rows, self.by_column) {
+            (_, Some(column)) => SplitBy::Column(column),
+            (Some(rows), None) => SplitBy::Rows(rows),
+            (None, None) => return Err(anyhow::anyhow!("Either --rows or --by-column is needed")),
+        };
+        let prefix = self.prefix.unwrap_or_else(|| {
+            Path::new(&self.input.input)
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .filter(|s| *s != "-")
+                .unwrap_or("split")
+                .to_string()
+        });
+        let reader = get_reader(&self.input.input)?;
+        let files = split_csv(
+            reader,
+            &self.input.read.read_options(),
+            &by,
+            &self.output_dir,
+            &prefix,
+        )?;
+        for file in files {
+            println!("{}", file.display());
+        }
+        Ok(())
+    }
+}
+
+impl CmdExecutor for MergeOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let read = self.read.read_options();
+        let inputs = self
+            .inputs
+            .into_iter()
+            .map(|path| Ok((path.clone(), get_reader(&path)?)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        merge_csv(inputs, get_writer(&self.output)?, &read)?;
+        Ok(())
+    }
+}
+
+impl CmdExecutor for StatsOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let reader = get_reader(&self.input.input)?;
+        let writer = get_writer(&self.output)?;
+        csv_stats(
+            reader,
+            writer,
+            &self.input.read.read_options(),
+            self.format,
+            &self.group_by,
+            &self.agg,
+        )?;
+        Ok(())
+    }
+}
+
+impl CmdExecutor for InspectOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let reader = get_reader(&self.input.input)?;
+        // guessing the encoding would hide invalid UTF-8
+        let read = self.input.read.read_options_with(InputEncoding::default());
+        let report = inspect_csv(reader, &read)?;
+        write_report(&report, get_writer(&self.output)?, self.format)?;
+        // the structured formats already carry the malformed rows
+        if !matches!(
+            self.format,
+            OutputFormat::Json | OutputFormat::Yaml | OutputFormat::Toml
+        ) {
+            for row in &report.malformed {
+                eprintln!("malformed row at {}", row);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl CmdExecutor for ValidateOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let schema = CsvSchema::load(&self.schema)?;
+        let reader = get_reader(&self.input.input)?;
+        let read = self.input.read.read_options_with(InputEncoding::default());
+        let report = validate_csv(reader, &read, &schema)?;
+        let mut lines: Vec<(u64, String)> = report
+            .malformed
+            .iter()
+            .map(|row| (row.line, row.to_string()))
+            .chain(report.errors.iter().map(|e| (e.line, e.to_string())))
+            .collect();
+        lines.sort_by_key(|(line, _)| *line);
+        for (_, line) in lines {
+            eprintln!("{}", line);
+        }
+        if report.invalid_rows > 0 {
+            return Err(anyhow::anyhow!(
+                "{} of {} rows failed validation",
+                report.invalid_rows,
+                report.rows
+            ));
+        }
+        println!("{} rows are valid", report.rows);
+        Ok(())
+    }
+}
+
+impl CmdExecutor for JoinOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let read = self.read.read_options();
+        let format = OutputFormat::resolve(self.format, &self.output, OutputFormat::Csv);
+        join_csv(
+            get_reader(&self.left)?,
+            get_reader(&self.right)?,
+            get_writer(&self.output)?,
+            &read,
+            &self.on,
+            self.how,
+            format,
+        )?;
+        Ok(())
+    }
+}
+
+impl CmdExecutor for QueryOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let query: SqlQuery = self.sql.parse()?;
+        let read = self.read.read_options();
+        let mut tables = HashMap::new();
+        for name in query.tables() {
+            let path = self
+                .files
+                .iter()
+                .find(|f| Path::new(f).file_stem().is_some_and(|s| s == name))
+                .cloned()
+                .or_else(|| {
+                    let path = format!("{}.csv", name);
+                    Path::new(&path).is_file().then_some(path)
+                })
+                .ok_or_else(|| anyhow::anyhow!("No csv file given for table {}", name))?;
+            tables.insert(name.to_string(), CsvTable::load(get_reader(&path)?, &read)?);
+        }
+        let format = OutputFormat::resolve(self.format, &self.output, OutputFormat::Table);
+        query.execute(&tables, get_writer(&self.output)?, format)?;
+        Ok(())
+    }
+}
+
+impl CmdExecutor for DiffOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let read = self.read.read_options();
+        let diff = diff_csv(
+            get_reader(&self.old)?,
+            get_reader(&self.new)?,
+            &read,
+            &self.key,
+        )?;
+        let labels = (self.old.as_str(), self.new.as_str());
+        write_diff(&diff, get_writer(&self.output)?, self.style, &read, labels)?;
+        // like diff(1), differences make the command fail
+        if !diff.is_empty() {
+            return Err(anyhow::anyhow!("{} and {} differ", self.old, self.new));
+        }
+        Ok(())
+    }
+}
+
 impl CmdExecutor for CsvOpts {
     async fn execute(self) -> anyhow::Result<()> {
-        process_csv(&self.input, &self.output, self.format)?;
+        let opts = CsvConvertOptions {
+            output_delimiter: single_byte(&self.output_delimiter)?,
+            read: CsvReadOptions {
+                delimiter: self.delimiter,
+                has_headers: self.header,
+                columns: self.columns,
+                encoding: self.encoding,
+                ..Default::default()
+            },
+            infer_types: self.infer,
+            schema: self.schema.as_deref().map(CsvSchema::load).transpose()?,
+            select: self.select,
+            rename: self.rename,
+            filters: self.filters,
+            sheet: self.sheet,
+            unflatten: self.unflatten,
+            verbose: self.verbose,
+            threads: self.threads,
+        };
+        let format = OutputFormat::resolve(self.format, &self.output, OutputFormat::Json);
+        process_csv(&self.input, &self.output, format, &opts)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_delimiter() {
+        assert_eq!(parse_delimiter(";").unwrap(), ";");
+        assert_eq!(parse_delimiter("\\t").unwrap(), "\t");
+        assert_eq!(parse_delimiter("tab").unwrap(), "\t");
+        assert_eq!(parse_delimiter("\\x1e").unwrap(), "\x1e");
+        assert_eq!(parse_delimiter("::").unwrap(), "::");
+        assert_eq!(parse_delimiter("¦").unwrap(), "¦");
+        assert!(parse_delimiter("").is_err());
+        assert!(parse_delimiter("\"").is_err());
+        assert!(parse_delimiter("\\q").is_err());
+    }
+
+    #[test]
+    fn test_format_from_extension() {
+        assert!(matches!(
+            OutputFormat::from_extension("out/players.YML"),
+            Some(OutputFormat::Yaml)
+        ));
+        assert!(matches!(
+            OutputFormat::from_extension("players.jsonl"),
+            Some(OutputFormat::Ndjson)
+        ));
+        assert!(OutputFormat::from_extension("-").is_none());
+        assert!(OutputFormat::from_extension("players.xml").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_utf8_is_reported_by_default() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("players.csv");
+        std::fs::write(&input, b"Name,Age\nBuf\xffon,41\nPerin,26\n")?;
+        let schema = dir.path().join("schema.json");
+        std::fs::write(&schema, r#"{"Age": {"type": "integer"}}"#)?;
+        let output = dir.path().join("report.json");
+        let path = |p: &std::path::Path| p.to_str().unwrap().to_string();
+
+        let opts = InspectOpts::try_parse_from([
+            "inspect",
+            "-i",
+            &path(&input),
+            "-o",
+            &path(&output),
+            "-f",
+            "json",
+        ])?;
+        opts.execute().await?;
+        let report: serde_json::Value = serde_json::from_slice(&std::fs::read(&output)?)?;
+        assert_eq!(report["malformed"][0]["line"], 2);
+
+        let opts = ValidateOpts::try_parse_from([
+            "validate",
+            "-i",
+            &path(&input),
+            "--schema",
+            &path(&schema),
+        ])?;
+        let err = opts.execute().await.unwrap_err();
+        assert_eq!(err.to_string(), "1 of 2 rows failed validation");
+
+        // guessing is still available when asked for
+        let opts = ValidateOpts::try_parse_from([
+            "validate",
+            "-i",
+            &path(&input),
+            "--schema",
+            &path(&schema),
+            "--encoding",
+            "auto",
+        ])?;
+        opts.execute().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_multi_file_commands_take_an_encoding() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        // utf-16 without a BOM, read as given rather than guessed
+        let utf16 =
+            |text: &str| -> Vec<u8> { text.encode_utf16().flat_map(|u| u.to_le_bytes()).collect() };
+        std::fs::write(path("players.csv"), utf16("id,name\n1,Buffon\n2,Perin\n"))?;
+        std::fs::write(path("clubs.csv"), utf16("id,club\n1,Juventus\n"))?;
+        let encoding = ["--encoding", "utf-16le"];
+
+        let args = [
+            "join",
+            &path("players.csv"),
+            &path("clubs.csv"),
+            "--on",
+            "id",
+        ];
+        let opts = JoinOpts::try_parse_from(
+            args.iter()
+                .chain(&encoding)
+                .chain(&["-o", &path("join.csv")]),
+        )?;
+        opts.execute().await?;
+        assert_eq!(
+            std::fs::read_to_string(path("join.csv"))?,
+            "id,name,club\n1,Buffon,Juventus\n"
+        );
+
+        let args = ["merge", &path("players.csv"), &path("players.csv")];
+        let opts = MergeOpts::try_parse_from(
+            args.iter()
+                .chain(&encoding)
+                .chain(&["-o", &path("merge.csv")]),
+        )?;
+        opts.execute().await?;
+        assert_eq!(
+            std::fs::read_to_string(path("merge.csv"))?.lines().count(),
+            5
+        );
+
+        let sql = "SELECT name FROM players WHERE id = 2";
+        let args = ["query", sql, &path("players.csv")];
+        let opts = QueryOpts::try_parse_from(
+            args.iter()
+                .chain(&encoding)
+                .chain(&["-o", &path("query.csv")]),
+        )?;
+        opts.execute().await?;
+        assert_eq!(std::fs::read_to_string(path("query.csv"))?, "name\nPerin\n");
+
+        let args = ["diff", &path("players.csv"), &path("players.csv")];
+        let opts = DiffOpts::try_parse_from(args.iter().chain(&encoding).chain(&["--key", "id"]))?;
+        opts.execute().await?;
         Ok(())
     }
 }
@@ -1,38 +1,488 @@
-use clap::Parser;
+use clap::{ArgAction, Parser};
+use enum_dispatch::enum_dispatch;
 
-use crate::CmdExecutor;
-use zxcvbn::zxcvbn;
+use super::{verify_file, OutputFormat};
+use crate::{
+    check_passwords, default_wordlist, generate_passphrase, generate_with_rng, get_content,
+    get_reader, get_secret_writer, get_writer, load_wordlist, make_rng, passphrase_entropy,
+    password_entropy, write_secrets, write_strength_reports, Charset, CmdExecutor, GeneratedSecret,
+    PassphraseOptions, PasswordPattern, PasswordPolicy, RngKind, SecretFormat,
+};
+use anyhow::anyhow;
+
+#[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct GenPassCommand {
+    #[command(subcommand)]
+    pub cmd: Option<GenPassSubcommand>,
+
+    #[command(flatten)]
+    pub opts: GenPassOpts,
+}
+
+#[derive(Debug, Parser)]
+#[enum_dispatch(CmdExecutor)]
+pub enum GenPassSubcommand {
+    #[command(
+        name = "check",
+        about = "Estimate the strength of passwords, one per line, with zxcvbn"
+    )]
+    Check(CheckOpts),
+}
+
+#[derive(Debug, Parser)]
+pub struct CheckOpts {
+    #[arg(short, long, default_value = "-", value_parser = verify_file)]
+    pub input: String,
+
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    /// Output format: table for text, json, ndjson or yaml
+    #[arg(short, long, default_value = "table")]
+    pub format: OutputFormat,
+
+    /// Words that make passwords easier to guess, like user names or the
+    /// company, e.g. `--user-inputs alice,acme`
+    #[arg(long, value_delimiter = ',')]
+    pub user_inputs: Vec<String>,
+
+    /// Fail when a password scores below this, from 0 to 4
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u8).range(0..=4))]
+    pub min_score: u8,
+}
+
+// options shaping random passwords, which templates and passphrases don't use
+const POLICY_ARGS: [&str; 11] = [
+    "charset",
+    "chars",
+    "upper",
+    "lower",
+    "number",
+    "symbol",
+    "min_upper",
+    "min_lower",
+    "min_digits",
+    "min_symbols",
+    "no_repeat",
+];
 
 #[derive(Debug, Parser)]
 pub struct GenPassOpts {
     #[arg(short, long, default_value_t = 16)]
     pub length: u8,
 
-    #[arg(long, default_value_t = true)]
+    /// Leave out uppercase letters
+    #[arg(long = "no-upper", action = ArgAction::SetFalse)]
     pub upper: bool,
 
-    #[arg(long, default_value_t = true)]
+    /// Leave out lowercase letters
+    #[arg(long = "no-lower", action = ArgAction::SetFalse)]
     pub lower: bool,
 
-    #[arg(long, default_value_t = true)]
+    /// Leave out digits
+    #[arg(long = "no-number", action = ArgAction::SetFalse)]
     pub number: bool,
 
-    #[arg(long, default_value_t = true)]
+    /// Leave out symbols
+    #[arg(long = "no-symbol", action = ArgAction::SetFalse)]
     pub symbol: bool,
+
+    /// Character set: default (no look-alikes), full (printable ASCII), hex or
+    /// custom, which uses the characters given with `--chars`
+    #[arg(long, default_value = "default")]
+    pub charset: Charset,
+
+    /// Characters of the custom charset, e.g. `--charset custom --chars abcdef123`
+    #[arg(long, default_value = "", allow_hyphen_values = true)]
+    pub chars: String,
+
+    /// Symbols to use instead of those of the charset, e.g. `--symbols "!@#-_"`
+    #[arg(long, allow_hyphen_values = true)]
+    pub symbols: Option<String>,
+
+    /// Characters never to use, e.g. `--exclude 0O1lI`
+    #[arg(long, default_value = "", allow_hyphen_values = true)]
+    pub exclude: String,
+
+    #[arg(long)]
+    pub min_upper: Option<usize>,
+
+    #[arg(long)]
+    pub min_lower: Option<usize>,
+
+    #[arg(long)]
+    pub min_digits: Option<usize>,
+
+    #[arg(long)]
+    pub min_symbols: Option<usize>,
+
+    /// Use every character at most once
+    #[arg(long)]
+    pub no_repeat: bool,
+
+    /// Random number generator: os, or chacha for reproducible output with `--seed`
+    #[arg(long, default_value = "os")]
+    pub rng: RngKind,
+
+    /// Seed of the chacha generator, only meant for test fixtures
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Template of the password, e.g. `Cvccvc-9999-!!`: C/c consonant, V/v
+    /// vowel, A/a letter, 9 digit, ! symbol, * letter or digit, anything else
+    /// is kept, use `\` to keep a placeholder character
+    #[arg(
+        long,
+        allow_hyphen_values = true,
+        conflicts_with_all = POLICY_ARGS,
+        conflicts_with_all = ["length", "pronounceable", "passphrase"]
+    )]
+    pub pattern: Option<PasswordPattern>,
+
+    /// Alternate consonants and vowels for a password that reads as syllables
+    #[arg(
+        long,
+        conflicts_with_all = POLICY_ARGS,
+        conflicts_with_all = ["symbols", "passphrase"]
+    )]
+    pub pronounceable: bool,
+
+    /// Generate a passphrase of random words instead of a password
+    #[arg(
+        long,
+        conflicts_with_all = POLICY_ARGS,
+        conflicts_with_all = ["length", "symbols", "exclude"]
+    )]
+    pub passphrase: bool,
+
+    /// Words in the passphrase
+    #[arg(long, default_value_t = 6, requires = "passphrase")]
+    pub words: usize,
+
+    #[arg(
+        long,
+        default_value = "-",
+        allow_hyphen_values = true,
+        requires = "passphrase"
+    )]
+    pub separator: String,
+
+    /// Uppercase the first letter of every word
+    #[arg(long, requires = "passphrase")]
+    pub capitalize: bool,
+
+    /// Wordlist with one word per line instead of the built-in one, EFF dice
+    /// lists work as they are
+    #[arg(long, value_parser = verify_file, requires = "passphrase")]
+    pub wordlist: Option<String>,
+
+    /// Passwords to generate, one per name by default with `--names`
+    #[arg(short, long)]
+    pub count: Option<usize>,
+
+    /// Names of the secrets, e.g. `--names DB_PASS,API_KEY`
+    #[arg(long, value_delimiter = ',', value_parser = parse_secret_name)]
+    pub names: Vec<String>,
+
+    /// Output format: text, json, csv or env, env by default for `.env` files
+    #[arg(long)]
+    pub format: Option<SecretFormat>,
+
+    /// Output file, only readable by its owner
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+}
+
+impl GenPassOpts {
+    fn count(&self) -> anyhow::Result<usize> {
+        let count = match (self.count, self.names.len()) {
+            (Some(count), 0) => count,
+            (None, 0) => 1,
+            (None, names) => names,
+            (Some(count), names) if count == names => count,
+            (Some(count), names) => {
+                return Err(anyhow!("Got {} names for {} passwords", names, count))
+            }
+        };
+        if count == 0 {
+            return Err(anyhow!("Count must be at least 1"));
+        }
+        Ok(count)
+    }
+
+    fn format(&self) -> SecretFormat {
+        match self.format {
+            Some(format) => format,
+            None if self.output.ends_with(".env") => SecretFormat::Env,
+            None => SecretFormat::Text,
+        }
+    }
+}
+
+impl CmdExecutor for GenPassCommand {
+    async fn execute(self) -> anyhow::Result<()> {
+        match self.cmd {
+            Some(cmd) => cmd.execute().await,
+            None => self.opts.execute().await,
+        }
+    }
+}
+
+impl CmdExecutor for CheckOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let user_inputs: Vec<&str> = self.user_inputs.iter().map(String::as_str).collect();
+        let reports = check_passwords(get_reader(&self.input)?, &user_inputs)?;
+        write_strength_reports(&reports, get_writer(&self.output)?, self.format)?;
+        let weak = reports.iter().filter(|r| r.score < self.min_score).count();
+        if weak > 0 {
+            return Err(anyhow!(
+                "{} of {} passwords scored below {}",
+                weak,
+                reports.len(),
+                self.min_score
+            ));
+        }
+        Ok(())
+    }
 }
 
 impl CmdExecutor for GenPassOpts {
     async fn execute(self) -> anyhow::Result<()> {
-        let ret = crate::generate_password(
-            self.length,
-            self.upper,
-            self.lower,
-            self.number,
-            self.symbol,
-        )?;
-        println!("{}", ret);
-        let estimate = zxcvbn(&ret, &[]);
-        eprintln!("Estimated password strength: {:?}", estimate.score());
+        let count = self.count()?;
+        let format = self.format();
+        if format == SecretFormat::Env && self.names.is_empty() {
+            return Err(anyhow!("The env format needs the names of the secrets"));
+        }
+        let mut rng = make_rng(self.rng, self.seed)?;
+        // templates trade entropy for structure, show what a random password gets
+        let (passwords, entropy, random_entropy) = if self.passphrase {
+            let text = self
+                .wordlist
+                .as_deref()
+                .map(|path| anyhow::Ok(String::from_utf8(get_content(path)?)?))
+                .transpose()?;
+            let wordlist = match &text {
+                Some(text) => load_wordlist(text)?,
+                None => default_wordlist(),
+            };
+            let opts = PassphraseOptions {
+                words: self.words,
+                separator: self.separator,
+                capitalize: self.capitalize,
+            };
+            let passwords = (0..count)
+                .map(|_| generate_passphrase(&opts, &wordlist, &mut rng))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            (
+                passwords,
+                passphrase_entropy(opts.words, wordlist.len()),
+                None,
+            )
+        } else {
+            let policy = PasswordPolicy {
+                length: self.length as usize,
+                charset: self.charset,
+                chars: self.chars,
+                upper: self.upper,
+                lower: self.lower,
+                number: self.number,
+                symbol: self.symbol,
+                symbols: self.symbols,
+                exclude: self.exclude,
+                min_upper: self.min_upper,
+                min_lower: self.min_lower,
+                min_digits: self.min_digits,
+                min_symbols: self.min_symbols,
+                no_repeat: self.no_repeat,
+                pattern: if self.pronounceable {
+                    if self.length == 0 {
+                        return Err(anyhow!("Length must be at least 1"));
+                    }
+                    Some(PasswordPattern::pronounceable(self.length as usize))
+                } else {
+                    self.pattern
+                },
+            };
+            let passwords = (0..count)
+                .map(|_| generate_with_rng(&policy, &mut rng))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let random_entropy = policy.pattern.as_ref().and_then(|_| {
+                let length = passwords[0].chars().count();
+                password_entropy(&PasswordPolicy {
+                    length,
+                    pattern: None,
+                    ..policy.clone()
+                })
+                .ok()
+            });
+            (passwords, password_entropy(&policy)?, random_entropy)
+        };
+
+        let secrets: Vec<_> = passwords
+            .into_iter()
+            .enumerate()
+            .map(|(i, password)| {
+                GeneratedSecret::new(self.names.get(i).cloned(), password, entropy)
+            })
+            .collect();
+        write_secrets(&secrets, get_secret_writer(&self.output)?, format)?;
+        if let [secret] = secrets.as_slice() {
+            if format == SecretFormat::Text {
+                eprintln!("Estimated password strength: {}", secret.score);
+                eprintln!("Entropy: {:.1} bits", secret.entropy);
+                if let Some(random) = random_entropy {
+                    eprintln!(
+                        "A random password of the same length has {:.1} bits",
+                        random
+                    );
+                }
+            }
+        }
         Ok(())
     }
 }
+
+// names must work as environment variables
+fn parse_secret_name(name: &str) -> anyhow::Result<String> {
+    let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(name.to_string())
+    } else {
+        Err(anyhow!("Invalid secret name: {:?}", name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disable_character_classes() {
+        let opts = GenPassOpts::try_parse_from(["genpass", "--no-symbol", "--no-upper"]).unwrap();
+        assert!(!opts.symbol && !opts.upper);
+        assert!(opts.lower && opts.number);
+
+        let opts = GenPassOpts::try_parse_from(["genpass"]).unwrap();
+        assert!(opts.upper && opts.lower && opts.number && opts.symbol);
+    }
+
+    #[test]
+    fn test_passphrase_options_need_passphrase() {
+        assert!(GenPassOpts::try_parse_from(["genpass", "--words", "4"]).is_err());
+        let opts = GenPassOpts::try_parse_from([
+            "genpass",
+            "--passphrase",
+            "--separator",
+            "-",
+            "--words",
+            "4",
+        ])
+        .unwrap();
+        assert_eq!((opts.words, opts.separator.as_str()), (4, "-"));
+        for flag in [
+            &["-l", "4"][..],
+            &["--charset", "hex"],
+            &["--no-repeat"],
+            &["--exclude", "a"],
+            &["--min-upper", "1"],
+            &["--no-number"],
+        ] {
+            let args = [&["genpass", "--passphrase"][..], flag].concat();
+            assert!(GenPassOpts::try_parse_from(args).is_err());
+        }
+    }
+
+    #[test]
+    fn test_count_and_names() {
+        let parse =
+            |args: &[&str]| GenPassOpts::try_parse_from(["genpass"].iter().chain(args)).unwrap();
+        let opts = parse(&[]);
+        assert_eq!(
+            (opts.count().unwrap(), opts.format()),
+            (1, SecretFormat::Text)
+        );
+        let opts = parse(&["--names", "DB_PASS,API_KEY", "-o", "prod.env"]);
+        assert_eq!(
+            (opts.count().unwrap(), opts.format()),
+            (2, SecretFormat::Env)
+        );
+        assert_eq!(opts.names, ["DB_PASS", "API_KEY"]);
+        let opts = parse(&["-c", "50", "--format", "csv"]);
+        assert_eq!(
+            (opts.count().unwrap(), opts.format()),
+            (50, SecretFormat::Csv)
+        );
+
+        assert!(parse(&["-c", "3", "--names", "A,B"]).count().is_err());
+        assert!(parse(&["-c", "0"]).count().is_err());
+        assert!(GenPassOpts::try_parse_from(["genpass", "--names", "DB-PASS"]).is_err());
+        assert!(GenPassOpts::try_parse_from(["genpass", "--format", "xml"]).is_err());
+    }
+
+    #[test]
+    fn test_check_subcommand() {
+        let cmd = GenPassCommand::try_parse_from(["genpass", "-l", "20"]).unwrap();
+        assert!(cmd.cmd.is_none());
+        assert_eq!(cmd.opts.length, 20);
+
+        let cmd = GenPassCommand::try_parse_from([
+            "genpass",
+            "check",
+            "--user-inputs",
+            "alice,acme",
+            "--min-score",
+            "4",
+        ])
+        .unwrap();
+        let Some(GenPassSubcommand::Check(opts)) = cmd.cmd else {
+            panic!("expected the check subcommand");
+        };
+        assert_eq!(opts.user_inputs, ["alice", "acme"]);
+        assert_eq!((opts.min_score, opts.input.as_str()), (4, "-"));
+
+        assert!(GenPassCommand::try_parse_from(["genpass", "check", "--min-score", "5"]).is_err());
+        assert!(GenPassCommand::try_parse_from(["genpass", "-l", "20", "check"]).is_err());
+    }
+
+    #[test]
+    fn test_pattern_and_pronounceable() {
+        let opts = GenPassOpts::try_parse_from(["genpass", "--pattern", "-Cvc-99"]).unwrap();
+        assert_eq!(opts.pattern, Some("-Cvc-99".parse().unwrap()));
+        assert!(GenPassOpts::try_parse_from(["genpass", "--pattern", ""]).is_err());
+        assert!(GenPassOpts::try_parse_from(["genpass", "--pattern", "cv", "-l", "8"]).is_err());
+        let opts = GenPassOpts::try_parse_from(["genpass", "--pronounceable", "-l", "8"]).unwrap();
+        assert_eq!(opts.length, 8);
+        for flag in [
+            &["--no-repeat"][..],
+            &["--min-digits", "2"],
+            &["--charset", "hex"],
+            &["--chars", "ab"],
+            &["--no-symbol"],
+        ] {
+            for mode in [&["--pattern", "99"][..], &["--pronounceable"]] {
+                let args = [&["genpass"][..], mode, flag].concat();
+                assert!(GenPassOpts::try_parse_from(args).is_err());
+            }
+        }
+        assert!(
+            GenPassOpts::try_parse_from(["genpass", "--pronounceable", "--symbols", "#"]).is_err()
+        );
+        let opts = GenPassOpts::try_parse_from(["genpass", "--pattern", "9!", "--exclude", "0"]);
+        assert!(opts.is_ok());
+        assert!(
+            GenPassOpts::try_parse_from(["genpass", "--pattern", "cv", "--pronounceable"]).is_err()
+        );
+        assert!(
+            GenPassOpts::try_parse_from(["genpass", "--pronounceable", "--passphrase"]).is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pronounceable_needs_a_length() {
+        let opts = GenPassOpts::try_parse_from(["genpass", "--pronounceable", "-l", "0"]).unwrap();
+        let err = opts.execute().await.unwrap_err();
+        assert_eq!(err.to_string(), "Length must be at least 1");
+    }
+}
@@ -7,8 +7,12 @@ mod text;
 use std::path::{Path, PathBuf};
 
 pub use base64::{Base64Format, Base64Subcommand, DecodeOpts, EncodeOpts};
-pub use csv::{CsvOpts, OutputFormat};
-pub use genpass::GenPassOpts;
+pub use csv::{
+    CsvCommand, CsvInputArgs, CsvOpts, CsvSubcommand, DedupOpts, DiffOpts, FromJsonOpts,
+    FromYamlOpts, InputFormat, InspectOpts, JoinOpts, MaskOpts, MergeOpts, OutputFormat, QueryOpts,
+    SortOpts, SplitOpts, StatsOpts, ValidateOpts,
+};
+pub use genpass::{CheckOpts, GenPassCommand, GenPassOpts, GenPassSubcommand};
 pub use http::HttpCommand;
 pub use http::HttpOpts;
 pub use text::*;
@@ -27,9 +31,12 @@ pub struct Opts {
 #[derive(Debug, Parser)]
 pub enum SubCommand {
     #[command(name = "csv", about = "Show CSV, or convert CSV to other formats")]
-    Csv(CsvOpts),
-    #[command(name = "genpass", about = "Generate a random password")]
-    Genpass(GenPassOpts),
+    Csv(Box<CsvCommand>),
+    #[command(
+        name = "genpass",
+        about = "Generate a random password, or check passwords"
+    )]
+    Genpass(Box<GenPassCommand>),
     #[clap(subcommand, about = "Base64 encode or decode")]
     Base64(Base64Subcommand),
     #[clap(subcommand, about = "Sign a file")]
@@ -60,7 +67,7 @@ pub fn verify_path(path: &str) -> Result<PathBuf, &'static str> {
 }
 
 pub fn verify_file(filename: &str) -> Result<String, &'static str> {
-    if filename == "-" || Path::new(filename).exists() {
+    if filename == "-" || Path::new(filename).is_file() {
         Ok(filename.into())
     } else {
         Err("File does not exist or is not a file")
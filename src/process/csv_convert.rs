@@ -1,25 +1,830 @@
-use std::fs;
-
-use crate::cli::OutputFormat;
-use anyhow::Result;
-use csv::Reader;
-
-pub fn process_csv(input: &str, output: &str, format: OutputFormat) -> Result<()> {
-    let mut reader = Reader::from_path(input)?;
-    let mut ret = Vec::with_capacity(128);
-    let header = reader.headers()?.clone();
-    for result in reader.records() {
-        let record = result?;
-        let json_value = header
-            .iter()
-            .zip(record.iter())
-            .collect::<serde_json::Value>();
-        ret.push(json_value);
-    }
-    let content = match format {
-        OutputFormat::Json => serde_json::to_string_pretty(&ret)?,
-        OutputFormat::Yaml => serde_yaml::to_string(&ret)?,
+use std::{
+    collections::HashSet,
+    io::{Read, Write},
+};
+
+use super::{
+    csv_output::{cell_text, record_writer, render_records},
+    csv_xlsx::{is_spreadsheet, read_sheet},
+    CoercionError, CsvReadOptions, CsvRecords, CsvSchema, Projection, RecordFilter, RecordTyper,
+};
+use crate::{
+    cli::{InputFormat, OutputFormat},
+    get_reader, get_writer,
+};
+use anyhow::{anyhow, Result};
+use csv::{ByteRecord, StringRecord, WriterBuilder};
+use rayon::{prelude::*, ThreadPoolBuilder};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+// show at most this many coercion failures in the error message
+const MAX_REPORTED_ERRORS: usize = 20;
+// records read ahead when converting in parallel
+const BATCH_SIZE: usize = 8 * 1024;
+// records a worker decodes, types and renders in one go
+const CHUNK_SIZE: usize = 256;
+
+#[derive(Debug, Clone)]
+pub struct CsvConvertOptions {
+    pub read: CsvReadOptions,
+    pub infer_types: bool,
+    pub schema: Option<CsvSchema>,
+    pub output_delimiter: u8,
+    pub select: Option<Vec<String>>,
+    pub rename: Vec<(String, String)>,
+    pub filters: Vec<String>,
+    // sheet to read when the input is a workbook
+    pub sheet: Option<String>,
+    // build nested objects and arrays from `a.b` and `a[0]` column names
+    pub unflatten: bool,
+    // report the input encoding on stderr
+    pub verbose: bool,
+    // worker threads converting records, 0 uses every core
+    pub threads: usize,
+}
+
+impl Default for CsvConvertOptions {
+    fn default() -> Self {
+        Self {
+            read: CsvReadOptions::default(),
+            infer_types: false,
+            schema: None,
+            output_delimiter: b',',
+            select: None,
+            rename: Vec::new(),
+            filters: Vec::new(),
+            sheet: None,
+            unflatten: false,
+            verbose: false,
+            threads: 1,
+        }
+    }
+}
+
+pub fn process_csv(
+    input: &str,
+    output: &str,
+    format: OutputFormat,
+    opts: &CsvConvertOptions,
+) -> Result<()> {
+    let writer = get_writer(output)?;
+    if is_spreadsheet(input) || opts.sheet.is_some() {
+        let sheet = read_sheet(input, opts.sheet.as_deref())?;
+        let opts = CsvConvertOptions {
+            read: CsvReadOptions {
+                delimiter: ",".into(),
+                ..opts.read.clone()
+            },
+            ..opts.clone()
+        };
+        convert_csv(sheet.as_slice(), writer, format, &opts)?;
+    } else {
+        convert_csv(get_reader(input)?, writer, format, opts)?;
+    }
+    Ok(())
+}
+
+// stream records from reader to writer one by one, returns the number of records converted
+pub fn convert_csv(
+    reader: impl Read,
+    writer: impl Write,
+    format: OutputFormat,
+    opts: &CsvConvertOptions,
+) -> Result<usize> {
+    let mut reader = CsvRecords::new(reader, &opts.read)?;
+    if opts.verbose {
+        eprintln!("Input encoding: {}", reader.encoding().name());
+    }
+    let headers = reader.headers();
+    let typer = RecordTyper::new(headers, opts.schema.as_ref(), opts.infer_types)?;
+    let filters = opts
+        .filters
+        .iter()
+        .map(|expr| RecordFilter::new(expr, headers))
+        .collect::<Result<Vec<_>>>()?;
+    let projection = Projection::new(headers, opts.select.as_deref(), &opts.rename)?;
+    let convert = |record: &StringRecord, errors: &mut ErrorLog| -> Result<_> {
+        if !filters.iter().all(|f| f.matches(record)) {
+            return Ok(None);
+        }
+        let mut failed = Vec::new();
+        let row = projection.apply(typer.to_value(record, &mut failed));
+        errors.push(failed);
+        let json_value = if opts.unflatten {
+            unflatten_object(row)?
+        } else {
+            Value::Object(row)
+        };
+        Ok(Some(json_value))
     };
-    fs::write(output, content)?;
+
+    let mut writer = record_writer(writer, format, opts.output_delimiter);
+    writer.set_columns(&projection.names());
+    let mut record = StringRecord::new();
+    let mut errors = ErrorLog::default();
+    let mut count = 0;
+    if opts.threads == 1 {
+        while reader.read_record(&mut record)? {
+            if let Some(json_value) = convert(&record, &mut errors)? {
+                writer.write_record(&json_value)?;
+                count += 1;
+            }
+        }
+    } else {
+        // the reader only splits the input into records, decoding, typing and
+        // rendering them runs on the pool in chunks that are written back in
+        // input order, so the output is the same as with a single thread
+        let pool = ThreadPoolBuilder::new().num_threads(opts.threads).build()?;
+        let mut batch: Vec<ByteRecord> = Vec::with_capacity(BATCH_SIZE);
+        let mut byte_record = ByteRecord::new();
+        // the first record starts the output and sets the csv columns, so it
+        // goes through the writer before anything is rendered ahead
+        let mut columns: Option<Vec<String>> = None;
+        loop {
+            batch.clear();
+            while batch.len() < BATCH_SIZE && reader.read_byte_record(&mut byte_record)? {
+                batch.push(byte_record.clone());
+            }
+            if batch.is_empty() {
+                break;
+            }
+            let mut rest = batch.as_slice();
+            while columns.is_none() {
+                let Some((first, tail)) = rest.split_first() else {
+                    break;
+                };
+                rest = tail;
+                if let Some(json_value) = convert(&string_record(first)?, &mut errors)? {
+                    columns = Some(match &json_value {
+                        Value::Object(map) => map.keys().cloned().collect(),
+                        _ => Vec::new(),
+                    });
+                    writer.write_record(&json_value)?;
+                    count += 1;
+                }
+            }
+            let columns = columns.as_deref().unwrap_or_default();
+            let chunks: Vec<_> = pool.install(|| {
+                rest.par_chunks(CHUNK_SIZE)
+                    .map(|chunk| {
+                        let mut errors = ErrorLog::default();
+                        let mut render = || -> Result<_> {
+                            let mut values = Vec::with_capacity(chunk.len());
+                            for record in chunk {
+                                if let Some(json_value) =
+                                    convert(&string_record(record)?, &mut errors)?
+                                {
+                                    values.push(json_value);
+                                }
+                            }
+                            let delimiter = opts.output_delimiter;
+                            Ok(match render_records(format, delimiter, columns, &values)? {
+                                Some(bytes) => Rendered::Bytes(bytes, values.len()),
+                                None => Rendered::Records(values),
+                            })
+                        };
+                        (render(), errors)
+                    })
+                    .collect()
+            });
+            for (rendered, chunk_errors) in chunks {
+                errors.append(chunk_errors);
+                match rendered? {
+                    Rendered::Bytes(bytes, records) => {
+                        writer.write_rendered(&bytes, records)?;
+                        count += records;
+                    }
+                    Rendered::Records(values) => {
+                        for json_value in &values {
+                            writer.write_record(json_value)?;
+                        }
+                        count += values.len();
+                    }
+                }
+            }
+        }
+    }
+    writer.finish()?;
+
+    if errors.total > 0 {
+        let mut report = format!("{} value(s) failed to match the schema:", errors.total);
+        for e in &errors.first {
+            report.push_str(&format!("\n  {}", e));
+        }
+        if errors.total > errors.first.len() {
+            report.push_str(&format!(
+                "\n  ... and {} more",
+                errors.total - errors.first.len()
+            ));
+        }
+        return Err(anyhow!(report));
+    }
+    Ok(count)
+}
+
+// coercion failures, only the first few are kept for the report
+#[derive(Default)]
+struct ErrorLog {
+    first: Vec<CoercionError>,
+    total: usize,
+}
+
+impl ErrorLog {
+    fn push(&mut self, errors: Vec<CoercionError>) {
+        self.total += errors.len();
+        let room = MAX_REPORTED_ERRORS - self.first.len();
+        self.first.extend(errors.into_iter().take(room));
+    }
+
+    // appends the failures of records that come after the ones logged so far
+    fn append(&mut self, other: ErrorLog) {
+        self.total += other.total;
+        let room = MAX_REPORTED_ERRORS - self.first.len();
+        self.first.extend(other.first.into_iter().take(room));
+    }
+}
+
+// a chunk converted on the pool, rendered unless the format needs every record
+enum Rendered {
+    Bytes(Vec<u8>, usize),
+    Records(Vec<Value>),
+}
+
+// decodes a record read as bytes, failing with the message the csv reader gives
+fn string_record(record: &ByteRecord) -> Result<StringRecord> {
+    StringRecord::from_byte_record(record.clone()).map_err(|e| {
+        let err = e.utf8_error();
+        match record.position() {
+            Some(pos) => anyhow!(
+                "CSV parse error: record {} (line {}, field: {}, byte: {}): {}",
+                pos.record(),
+                pos.line(),
+                err.field(),
+                pos.byte(),
+                err
+            ),
+            None => anyhow!("CSV parse error: field {}: {}", err.field(), err),
+        }
+    })
+}
+
+pub fn process_to_csv(
+    input: &str,
+    output: &str,
+    format: InputFormat,
+    delimiter: u8,
+    flatten_arrays: bool,
+) -> Result<()> {
+    let reader = get_reader(input)?;
+    let writer = get_writer(output)?;
+    records_to_csv(reader, writer, format, delimiter, flatten_arrays)?;
     Ok(())
 }
+
+// flatten JSON/YAML records into csv rows, the header is the union of all keys
+// in the order they were first seen; arrays are written as JSON text unless
+// `flatten_arrays` is set; returns the number of rows written
+pub fn records_to_csv(
+    reader: impl Read,
+    writer: impl Write,
+    format: InputFormat,
+    delimiter: u8,
+    flatten_arrays: bool,
+) -> Result<usize> {
+    let records = read_records(reader, format)?;
+    let mut headers = Vec::new();
+    let mut seen = HashSet::new();
+    let mut rows = Vec::with_capacity(records.len());
+    for (i, record) in records.iter().enumerate() {
+        let row = match record {
+            Value::Object(map) => flatten_object(map, flatten_arrays),
+            _ => return Err(anyhow!("Record {} is not an object", i + 1)),
+        };
+        for key in row.keys() {
+            if seen.insert(key.clone()) {
+                headers.push(key.clone());
+            }
+        }
+        rows.push(row);
+    }
+
+    let mut writer = WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(writer);
+    writer.write_record(&headers)?;
+    for row in &rows {
+        writer.write_record(
+            headers
+                .iter()
+                .map(|h| row.get(h).map(cell_text).unwrap_or_default().into_owned()),
+        )?;
+    }
+    writer.flush()?;
+    Ok(rows.len())
+}
+
+// accepts a single array, a stream of objects (NDJSON), or any mix of both;
+// for YAML every document is treated the same way
+fn read_records(reader: impl Read, format: InputFormat) -> Result<Vec<Value>> {
+    let mut records = Vec::new();
+    let mut push = |value: Value| match value {
+        Value::Array(items) => records.extend(items),
+        value => records.push(value),
+    };
+    match format {
+        InputFormat::Json => {
+            for value in serde_json::Deserializer::from_reader(reader).into_iter::<Value>() {
+                push(value?);
+            }
+        }
+        InputFormat::Yaml => {
+            for doc in serde_yaml::Deserializer::from_reader(reader) {
+                push(Value::deserialize(doc)?);
+            }
+        }
+    }
+    Ok(records)
+}
+
+// nested objects become dotted column names, e.g. `{"a": {"b": 1}}` => `a.b`,
+// with `arrays` set `{"a": [1, 2]}` becomes `a[0]` and `a[1]`
+pub fn flatten_object(map: &Map<String, Value>, arrays: bool) -> Map<String, Value> {
+    let mut ret = Map::new();
+    for (key, value) in map {
+        flatten_into(key.clone(), value, arrays, &mut ret);
+    }
+    ret
+}
+
+fn flatten_into(prefix: String, value: &Value, arrays: bool, ret: &mut Map<String, Value>) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (key, value) in map {
+                flatten_into(format!("{}.{}", prefix, key), value, arrays, ret);
+            }
+        }
+        Value::Array(items) if arrays && !items.is_empty() => {
+            for (i, value) in items.iter().enumerate() {
+                flatten_into(format!("{}[{}]", prefix, i), value, arrays, ret);
+            }
+        }
+        value => {
+            ret.insert(prefix, value.clone());
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+/// The inverse of `flatten_object`: `address.city` becomes a nested object and
+/// `tags[0]` an array element. Gaps in arrays are filled with nulls and
+/// trailing nulls are dropped, so rows with fewer items than the widest one
+/// get shorter arrays. Names that don't parse as a path are kept as they are.
+pub fn unflatten_object(map: Map<String, Value>) -> Result<Value> {
+    let mut ret = Value::Object(Map::new());
+    for (key, value) in map {
+        let path = parse_path(&key).unwrap_or_else(|| vec![PathSegment::Key(key.clone())]);
+        insert_path(&mut ret, &path, value)
+            .ok_or_else(|| anyhow!("Column {:?} conflicts with another column", key))?;
+    }
+    trim_arrays(&mut ret);
+    Ok(ret)
+}
+
+// `a.b[0][1]` => [Key(a), Key(b), Index(0), Index(1)]
+fn parse_path(key: &str) -> Option<Vec<PathSegment>> {
+    let mut path = Vec::new();
+    for part in key.split('.') {
+        let (name, mut rest) = part.split_at(part.find('[').unwrap_or(part.len()));
+        if name.is_empty() {
+            return None;
+        }
+        path.push(PathSegment::Key(name.to_string()));
+        while !rest.is_empty() {
+            let (index, tail) = rest.strip_prefix('[')?.split_once(']')?;
+            path.push(PathSegment::Index(index.parse().ok()?));
+            rest = tail;
+        }
+    }
+    Some(path)
+}
+
+// returns None when the path runs into a value of another shape, e.g. `a` and `a.b`
+fn insert_path(target: &mut Value, path: &[PathSegment], value: Value) -> Option<()> {
+    let Some((segment, rest)) = path.split_first() else {
+        if !target.is_null() {
+            return None;
+        }
+        *target = value;
+        return Some(());
+    };
+    match segment {
+        PathSegment::Key(key) => {
+            if target.is_null() {
+                *target = Value::Object(Map::new());
+            }
+            let child = target
+                .as_object_mut()?
+                .entry(key.clone())
+                .or_insert(Value::Null);
+            insert_path(child, rest, value)
+        }
+        PathSegment::Index(i) => {
+            if target.is_null() {
+                *target = Value::Array(Vec::new());
+            }
+            let items = target.as_array_mut()?;
+            if items.len() <= *i {
+                items.resize(i + 1, Value::Null);
+            }
+            insert_path(&mut items[*i], rest, value)
+        }
+    }
+}
+
+fn trim_arrays(value: &mut Value) {
+    match value {
+        Value::Object(map) => map.values_mut().for_each(trim_arrays),
+        Value::Array(items) => {
+            items.iter_mut().for_each(trim_arrays);
+            while items.last().is_some_and(Value::is_null) {
+                items.pop();
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use csv::Reader;
+    use serde_json::{json, Value};
+    use std::fs::File;
+
+    fn convert_fixture(input: &str, opts: &CsvConvertOptions) -> Result<Value> {
+        let mut buf = Vec::new();
+        convert_csv(File::open(input)?, &mut buf, OutputFormat::Json, opts)?;
+        Ok(serde_json::from_slice(&buf)?)
+    }
+
+    fn with_read(read: CsvReadOptions) -> CsvConvertOptions {
+        CsvConvertOptions {
+            read,
+            ..Default::default()
+        }
+    }
+
+    fn with_delimiter(delimiter: &str) -> CsvConvertOptions {
+        with_read(CsvReadOptions {
+            delimiter: delimiter.into(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_convert_csv_matches_buffered_output() -> Result<()> {
+        let input = "assets/juventus.csv";
+        let opts = CsvConvertOptions::default();
+        let mut reader = Reader::from_path(input)?;
+        let header = reader.headers()?.clone();
+        let mut expected = Vec::new();
+        for result in reader.records() {
+            let record = result?;
+            expected.push(
+                header
+                    .iter()
+                    .zip(record.iter())
+                    .collect::<serde_json::Value>(),
+            );
+        }
+
+        let mut buf = Vec::new();
+        let count = convert_csv(File::open(input)?, &mut buf, OutputFormat::Json, &opts)?;
+        assert_eq!(count, 27);
+        assert_eq!(
+            String::from_utf8(buf)?,
+            serde_json::to_string_pretty(&expected)?
+        );
+
+        let mut buf = Vec::new();
+        convert_csv(File::open(input)?, &mut buf, OutputFormat::Yaml, &opts)?;
+        assert_eq!(String::from_utf8(buf)?, serde_yaml::to_string(&expected)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_csv_with_delimiters() -> Result<()> {
+        let expected = convert_fixture("fixtures/players.csv", &Default::default())?;
+        assert_eq!(expected[1]["Name"], "Perin, Mattia");
+        for (input, delimiter) in [
+            ("fixtures/players.tsv", "\t"),
+            ("fixtures/players_semicolon.csv", ";"),
+            ("fixtures/players_pipe.csv", "|"),
+            ("fixtures/players_multi.csv", "::"),
+        ] {
+            assert_eq!(
+                convert_fixture(input, &with_delimiter(delimiter))?,
+                expected
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_csv_without_header() -> Result<()> {
+        let mut opts = with_read(CsvReadOptions {
+            has_headers: false,
+            ..Default::default()
+        });
+        let ret = convert_fixture("fixtures/players_noheader.csv", &opts)?;
+        assert_eq!(
+            ret[0],
+            json!({"col_0": "Szczesny, Wojciech", "col_1": "Goalkeeper", "col_2": "1"})
+        );
+
+        opts.read.columns = Some(vec!["name".into(), "position".into(), "number".into()]);
+        let ret = convert_fixture("fixtures/players_noheader.csv", &opts)?;
+        assert_eq!(ret.as_array().map(Vec::len), Some(3));
+        assert_eq!(
+            ret[2],
+            json!({"name": "Buffon, Gianluigi", "position": "Goalkeeper", "number": "77"})
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_csv_header_only() -> Result<()> {
+        let convert = |opts: &CsvConvertOptions| {
+            let mut buf = Vec::new();
+            convert_csv(
+                "Name,Position\n".as_bytes(),
+                &mut buf,
+                OutputFormat::Csv,
+                opts,
+            )?;
+            anyhow::Ok(String::from_utf8(buf)?)
+        };
+        assert_eq!(convert(&Default::default())?, "Name,Position\n");
+        let opts = CsvConvertOptions {
+            select: Some(vec!["Position".into()]),
+            rename: vec![("Position".into(), "Role".into())],
+            ..Default::default()
+        };
+        assert_eq!(convert(&opts)?, "Role\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_csv_with_types() -> Result<()> {
+        let mut opts = CsvConvertOptions {
+            infer_types: true,
+            ..Default::default()
+        };
+        let ret = convert_fixture("fixtures/typed.csv", &opts)?;
+        assert_eq!(
+            ret[0],
+            json!({"id": 1, "name": "Buffon", "height": 1.92, "active": true, "joined": "2001-07-03", "zip": "01234"})
+        );
+        assert_eq!(ret[1]["height"], Value::Null);
+
+        opts.schema = Some(CsvSchema::load("fixtures/typed_schema.json")?);
+        let err = convert_fixture("fixtures/typed.csv", &opts).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "1 value(s) failed to match the schema:\n  line 4, column 1 (id): \"three\" is not a valid integer"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_records_to_csv() -> Result<()> {
+        let expected = "\
+name,club.name,club.city,number,tags,loan
+Buffon,Juventus,Turin,77,\"[\"\"captain\"\"]\",
+Perin,Juventus,Turin,37,[],true
+Pinsoglio,,,,,
+";
+        for (input, format) in [
+            ("fixtures/players.json", InputFormat::Json),
+            ("fixtures/players.ndjson", InputFormat::Json),
+            ("fixtures/players.yaml", InputFormat::Yaml),
+        ] {
+            let mut buf = Vec::new();
+            let count = records_to_csv(File::open(input)?, &mut buf, format, b',', false)?;
+            assert_eq!(count, 3);
+            assert_eq!(String::from_utf8(buf)?, expected, "{}", input);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_records_to_csv_rejects_scalars() {
+        let ret = records_to_csv(
+            &b"[{\"a\": 1}, 2]"[..],
+            Vec::new(),
+            InputFormat::Json,
+            b',',
+            false,
+        );
+        assert_eq!(ret.unwrap_err().to_string(), "Record 2 is not an object");
+    }
+
+    #[test]
+    fn test_parallel_output_is_identical() -> Result<()> {
+        let mut input = String::from("id,name,score,joined,active\n");
+        for i in 0..BATCH_SIZE + 17 {
+            input.push_str(&format!(
+                "{},player {},{}.5,2020-01-{:02},{}\n",
+                i,
+                i,
+                i % 97,
+                i % 28 + 1,
+                i % 3 == 0
+            ));
+        }
+        let convert = |format: OutputFormat, filter: &str, threads: usize| {
+            let opts = CsvConvertOptions {
+                infer_types: true,
+                filters: vec![filter.into()],
+                threads,
+                ..Default::default()
+            };
+            let mut buf = Vec::new();
+            let count = convert_csv(input.as_bytes(), &mut buf, format, &opts)?;
+            anyhow::Ok((count, buf))
+        };
+        // the second filter drops the whole first batch
+        let last_batch = format!("id >= {}", BATCH_SIZE);
+        for filter in ["score > 10", last_batch.as_str()] {
+            for format in [
+                OutputFormat::Json,
+                OutputFormat::Yaml,
+                OutputFormat::Toml,
+                OutputFormat::Ndjson,
+                OutputFormat::Csv,
+                OutputFormat::Table,
+            ] {
+                let serial = convert(format, filter, 1)?;
+                assert!(serial.0 > 0);
+                assert_eq!(convert(format, filter, 4)?, serial);
+                assert_eq!(convert(format, filter, 0)?, serial);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallel_reports_invalid_utf8_like_serial() {
+        let mut input = b"id,name\n".to_vec();
+        for i in 0..CHUNK_SIZE * 3 {
+            input.extend(format!("{},player\n", i).bytes());
+        }
+        input.extend(b"7,Buf\xffon\n");
+        let convert = |threads| {
+            let opts = CsvConvertOptions {
+                threads,
+                ..Default::default()
+            };
+            convert_csv(input.as_slice(), Vec::new(), OutputFormat::Csv, &opts)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(convert(4), convert(1));
+    }
+
+    #[test]
+    fn test_parallel_reports_errors_in_order() {
+        let mut input = String::from("id\n");
+        for i in 0..BATCH_SIZE + 5 {
+            input.push_str(if i % 1000 == 0 { "x\n" } else { "1\n" });
+        }
+        let schema: CsvSchema = serde_json::from_str(r#"{"id": "integer"}"#).unwrap();
+        let convert = |threads| {
+            let opts = CsvConvertOptions {
+                schema: Some(schema.clone()),
+                threads,
+                ..Default::default()
+            };
+            convert_csv(input.as_bytes(), Vec::new(), OutputFormat::Json, &opts)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(convert(3), convert(1));
+    }
+
+    #[test]
+    fn test_only_first_errors_are_kept() {
+        let mut input = String::from("id\n");
+        for _ in 0..BATCH_SIZE * 2 {
+            input.push_str("x\n");
+        }
+        let schema: CsvSchema = serde_json::from_str(r#"{"id": "integer"}"#).unwrap();
+        for threads in [1, 4] {
+            let opts = CsvConvertOptions {
+                schema: Some(schema.clone()),
+                threads,
+                ..Default::default()
+            };
+            let err = convert_csv(input.as_bytes(), Vec::new(), OutputFormat::Ndjson, &opts)
+                .unwrap_err()
+                .to_string();
+            let lines: Vec<_> = err.lines().collect();
+            assert_eq!(
+                lines[0],
+                format!("{} value(s) failed to match the schema:", BATCH_SIZE * 2)
+            );
+            assert_eq!(lines.len(), MAX_REPORTED_ERRORS + 2);
+            assert!(lines[1].starts_with("  line 2,"));
+            assert_eq!(
+                lines[MAX_REPORTED_ERRORS + 1],
+                format!("  ... and {} more", BATCH_SIZE * 2 - MAX_REPORTED_ERRORS)
+            );
+        }
+    }
+
+    #[test]
+    fn test_unflatten_round_trip() -> Result<()> {
+        let input = "\
+name,address.city,address.zip,tags[0],tags[1]
+Buffon,Turin,10151,captain,veteran
+Perin,Genoa,,keeper,
+";
+        let opts = CsvConvertOptions {
+            infer_types: true,
+            unflatten: true,
+            ..Default::default()
+        };
+        let mut json = Vec::new();
+        convert_csv(input.as_bytes(), &mut json, OutputFormat::Json, &opts)?;
+        let value: Value = serde_json::from_slice(&json)?;
+        assert_eq!(
+            value,
+            json!([
+                {
+                    "name": "Buffon",
+                    "address": {"city": "Turin", "zip": 10151},
+                    "tags": ["captain", "veteran"],
+                },
+                {
+                    "name": "Perin",
+                    "address": {"city": "Genoa", "zip": null},
+                    "tags": ["keeper"],
+                },
+            ])
+        );
+
+        let mut csv = Vec::new();
+        records_to_csv(json.as_slice(), &mut csv, InputFormat::Json, b',', true)?;
+        assert_eq!(String::from_utf8(csv)?, input);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unflatten_paths() {
+        let row = |pairs: &[(&str, Value)]| {
+            let map = pairs.iter().map(|(k, v)| (k.to_string(), v.clone()));
+            unflatten_object(map.collect())
+        };
+        assert_eq!(
+            row(&[("m[1][0]", json!(1)), ("m[2]", Value::Null)]).unwrap(),
+            json!({"m": [null, [1]]})
+        );
+        assert_eq!(
+            row(&[("a[x]", json!(1)), ("b..c", json!(2))]).unwrap(),
+            json!({"a[x]": 1, "b..c": 2})
+        );
+        let err = row(&[("a", json!(1)), ("a.b", json!(2))]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Column \"a.b\" conflicts with another column"
+        );
+        assert!(row(&[("a[0]", json!(1)), ("a.b", json!(2))]).is_err());
+    }
+
+    #[test]
+    fn test_convert_csv_select_and_filter() -> Result<()> {
+        let opts = CsvConvertOptions {
+            infer_types: true,
+            select: Some(vec!["Name".into(), "4".into()]),
+            rename: vec![("Kit Number".into(), "number".into())],
+            filters: vec!["Nationality == Italy".into(), "Position =~ '^Goal'".into()],
+            ..Default::default()
+        };
+        let ret = convert_fixture("assets/juventus.csv", &opts)?;
+        assert_eq!(
+            ret,
+            json!([
+                {"Name": "Mattia Perin", "number": 37},
+                {"Name": "Gianluigi Buffon", "number": 77},
+                {"Name": "Carlo Pinsoglio", "number": 31},
+            ])
+        );
+        Ok(())
+    }
+}
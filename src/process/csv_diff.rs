@@ -0,0 +1,489 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Read, Write},
+    str::FromStr,
+};
+
+use super::{csv_filter::column_index, csv_sort::csv_writer, CsvReadOptions, CsvRecords};
+use anyhow::{anyhow, Result};
+use csv::StringRecord;
+use serde_json::{json, Map, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffStyle {
+    Human,
+    Json,
+    Unified,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnChange {
+    pub column: String,
+    pub old: String,
+    pub new: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RowChange {
+    Added {
+        line: u64,
+        record: StringRecord,
+    },
+    Removed {
+        line: u64,
+        record: StringRecord,
+    },
+    Modified {
+        old_line: u64,
+        new_line: u64,
+        old: StringRecord,
+        new: StringRecord,
+        changes: Vec<ColumnChange>,
+    },
+}
+
+/// Differences between two versions of a csv file, rows are matched by key.
+#[derive(Debug, Clone)]
+pub struct CsvDiff {
+    pub old_headers: StringRecord,
+    pub new_headers: StringRecord,
+    pub added_columns: Vec<String>,
+    pub removed_columns: Vec<String>,
+    // the key of each changed row, e.g. `[("id", "3")]`
+    pub rows: Vec<(Vec<(String, String)>, RowChange)>,
+}
+
+impl CsvDiff {
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty() && self.added_columns.is_empty() && self.removed_columns.is_empty()
+    }
+
+    fn count(&self, kind: &str) -> usize {
+        self.rows
+            .iter()
+            .filter(|(_, change)| change.kind() == kind)
+            .count()
+    }
+}
+
+impl RowChange {
+    fn kind(&self) -> &'static str {
+        match self {
+            RowChange::Added { .. } => "added",
+            RowChange::Removed { .. } => "removed",
+            RowChange::Modified { .. } => "modified",
+        }
+    }
+}
+
+/// Compares two csv inputs keyed by the `keys` columns. The old input is held
+/// in memory and the new one streamed; changed and added rows are reported in
+/// the order of the new input, followed by the removed rows in their old order.
+/// Only columns present in both inputs are compared cell by cell.
+pub fn diff_csv(
+    old: impl Read,
+    new: impl Read,
+    read: &CsvReadOptions,
+    keys: &[String],
+) -> Result<CsvDiff> {
+    if keys.is_empty() {
+        return Err(anyhow!("At least one key column is needed"));
+    }
+    let mut old = CsvRecords::new(old, read)?;
+    let mut new = CsvRecords::new(new, read)?;
+    let old_headers = old.headers().clone();
+    let new_headers = new.headers().clone();
+    let old_keys = keys
+        .iter()
+        .map(|k| column_index(&old_headers, k))
+        .collect::<Result<Vec<_>>>()?;
+    let new_keys = keys
+        .iter()
+        .map(|k| column_index(&new_headers, k))
+        .collect::<Result<Vec<_>>>()?;
+    // (column name, index in old, index in new) of the columns both sides share
+    let shared: Vec<(&str, usize, usize)> = old_headers
+        .iter()
+        .enumerate()
+        .filter_map(|(i, name)| Some((name, i, new_headers.iter().position(|n| n == name)?)))
+        .collect();
+
+    let mut old_rows: Vec<Option<StringRecord>> = Vec::new();
+    let mut index: HashMap<Vec<String>, usize> = HashMap::new();
+    let mut record = StringRecord::new();
+    while old.read_record(&mut record)? {
+        let key = key_of(&record, &old_keys);
+        if index.insert(key.clone(), old_rows.len()).is_some() {
+            return Err(duplicate_key("old", keys, &key, &record));
+        }
+        old_rows.push(Some(record.clone()));
+    }
+
+    let mut rows = Vec::new();
+    let mut seen = HashSet::new();
+    while new.read_record(&mut record)? {
+        let key = key_of(&record, &new_keys);
+        let new_line = line_of(&record);
+        if !seen.insert(key.clone()) {
+            return Err(duplicate_key("new", keys, &key, &record));
+        }
+        let named_key: Vec<(String, String)> = keys.iter().cloned().zip(key.clone()).collect();
+        match index.get(&key).and_then(|&i| old_rows[i].take()) {
+            None => rows.push((
+                named_key,
+                RowChange::Added {
+                    line: new_line,
+                    record: record.clone(),
+                },
+            )),
+            Some(old_record) => {
+                let changes: Vec<ColumnChange> = shared
+                    .iter()
+                    .filter_map(|&(name, i, j)| {
+                        let (a, b) = (old_record.get(i)?, record.get(j)?);
+                        (a != b).then(|| ColumnChange {
+                            column: name.to_string(),
+                            old: a.to_string(),
+                            new: b.to_string(),
+                        })
+                    })
+                    .collect();
+                if !changes.is_empty() {
+                    rows.push((
+                        named_key,
+                        RowChange::Modified {
+                            old_line: line_of(&old_record),
+                            new_line,
+                            old: old_record,
+                            new: record.clone(),
+                            changes,
+                        },
+                    ));
+                }
+            }
+        }
+    }
+    for record in old_rows.into_iter().flatten() {
+        let key = keys
+            .iter()
+            .cloned()
+            .zip(key_of(&record, &old_keys))
+            .collect();
+        rows.push((
+            key,
+            RowChange::Removed {
+                line: line_of(&record),
+                record,
+            },
+        ));
+    }
+
+    Ok(CsvDiff {
+        added_columns: new_headers
+            .iter()
+            .filter(|n| !old_headers.iter().any(|o| o == *n))
+            .map(String::from)
+            .collect(),
+        removed_columns: old_headers
+            .iter()
+            .filter(|o| !new_headers.iter().any(|n| n == *o))
+            .map(String::from)
+            .collect(),
+        old_headers,
+        new_headers,
+        rows,
+    })
+}
+
+fn key_of(record: &StringRecord, columns: &[usize]) -> Vec<String> {
+    columns
+        .iter()
+        .map(|&i| record.get(i).unwrap_or_default().to_string())
+        .collect()
+}
+
+fn line_of(record: &StringRecord) -> u64 {
+    record.position().map_or(0, |p| p.line())
+}
+
+fn duplicate_key(
+    side: &str,
+    keys: &[String],
+    key: &[String],
+    record: &StringRecord,
+) -> anyhow::Error {
+    anyhow!(
+        "Duplicate key {} in the {} file at line {}",
+        key_text(
+            &keys
+                .iter()
+                .cloned()
+                .zip(key.iter().cloned())
+                .collect::<Vec<_>>()
+        ),
+        side,
+        line_of(record)
+    )
+}
+
+fn key_text(key: &[(String, String)]) -> String {
+    key.iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn record_object(headers: &StringRecord, record: &StringRecord) -> Value {
+    Value::Object(
+        headers
+            .iter()
+            .zip(record.iter())
+            .map(|(h, v)| (h.to_string(), Value::String(v.to_string())))
+            .collect(),
+    )
+}
+
+// `labels` name the old and new input in the unified style header
+pub fn write_diff(
+    diff: &CsvDiff,
+    mut writer: impl Write,
+    style: DiffStyle,
+    read: &CsvReadOptions,
+    labels: (&str, &str),
+) -> Result<()> {
+    match style {
+        DiffStyle::Human => {
+            if !diff.added_columns.is_empty() {
+                writeln!(writer, "columns added: {}", diff.added_columns.join(", "))?;
+            }
+            if !diff.removed_columns.is_empty() {
+                writeln!(
+                    writer,
+                    "columns removed: {}",
+                    diff.removed_columns.join(", ")
+                )?;
+            }
+            for (key, change) in &diff.rows {
+                let key = key_text(key);
+                match change {
+                    RowChange::Added { line, .. } => writeln!(writer, "+ {} (line {})", key, line)?,
+                    RowChange::Removed { line, .. } => {
+                        writeln!(writer, "- {} (line {})", key, line)?
+                    }
+                    RowChange::Modified { changes, .. } => {
+                        writeln!(writer, "~ {}", key)?;
+                        for c in changes {
+                            writeln!(writer, "    {}: {:?} -> {:?}", c.column, c.old, c.new)?;
+                        }
+                    }
+                }
+            }
+            writeln!(
+                writer,
+                "{} added, {} removed, {} modified",
+                diff.count("added"),
+                diff.count("removed"),
+                diff.count("modified")
+            )?;
+        }
+        DiffStyle::Json => {
+            let mut groups: Map<String, Value> = ["added", "removed", "modified"]
+                .iter()
+                .map(|k| (k.to_string(), Value::Array(Vec::new())))
+                .collect();
+            for (key, change) in &diff.rows {
+                let key: Map<String, Value> = key
+                    .iter()
+                    .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+                    .collect();
+                let entry = match change {
+                    RowChange::Added { record, .. } => {
+                        json!({"key": key, "row": record_object(&diff.new_headers, record)})
+                    }
+                    RowChange::Removed { record, .. } => {
+                        json!({"key": key, "row": record_object(&diff.old_headers, record)})
+                    }
+                    RowChange::Modified { changes, .. } => {
+                        let changes: Map<String, Value> = changes
+                            .iter()
+                            .map(|c| (c.column.clone(), json!({"old": c.old, "new": c.new})))
+                            .collect();
+                        json!({"key": key, "changes": changes})
+                    }
+                };
+                if let Some(Value::Array(list)) = groups.get_mut(change.kind()) {
+                    list.push(entry);
+                }
+            }
+            groups.insert("added_columns".into(), json!(diff.added_columns));
+            groups.insert("removed_columns".into(), json!(diff.removed_columns));
+            serde_json::to_writer_pretty(&mut writer, &groups)?;
+            writeln!(writer)?;
+        }
+        DiffStyle::Unified => {
+            writeln!(writer, "--- {}\n+++ {}", labels.0, labels.1)?;
+            if diff.old_headers != diff.new_headers {
+                writeln!(writer, "@@ -1,1 +1,1 @@ header")?;
+                write_line(&mut writer, '-', &diff.old_headers, read)?;
+                write_line(&mut writer, '+', &diff.new_headers, read)?;
+            }
+            for (key, change) in &diff.rows {
+                let key = key_text(key);
+                match change {
+                    RowChange::Added { line, record } => {
+                        writeln!(writer, "@@ -0,0 +{},1 @@ {}", line, key)?;
+                        write_line(&mut writer, '+', record, read)?;
+                    }
+                    RowChange::Removed { line, record } => {
+                        writeln!(writer, "@@ -{},1 +0,0 @@ {}", line, key)?;
+                        write_line(&mut writer, '-', record, read)?;
+                    }
+                    RowChange::Modified {
+                        old_line,
+                        new_line,
+                        old,
+                        new,
+                        ..
+                    } => {
+                        writeln!(writer, "@@ -{},1 +{},1 @@ {}", old_line, new_line, key)?;
+                        write_line(&mut writer, '-', old, read)?;
+                        write_line(&mut writer, '+', new, read)?;
+                    }
+                }
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// a record as it would appear in the file, prefixed with the diff marker
+fn write_line(
+    writer: &mut impl Write,
+    marker: char,
+    record: &StringRecord,
+    read: &CsvReadOptions,
+) -> Result<()> {
+    let mut csv = csv_writer(Vec::new(), read);
+    csv.write_record(record)?;
+    let line = csv.into_inner()?;
+    write!(writer, "{}", marker)?;
+    writer.write_all(&line)?;
+    Ok(())
+}
+
+impl FromStr for DiffStyle {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" | "text" => Ok(DiffStyle::Human),
+            "json" => Ok(DiffStyle::Json),
+            "unified" | "diff" => Ok(DiffStyle::Unified),
+            _ => Err(anyhow!("Invalid diff style: {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD: &str = "\
+id,name,age,fax
+1,Buffon,41,x
+2,Perin,26,y
+3,Szczesny,29,z
+";
+    const NEW: &str = "\
+id,name,age,email
+3,Szczesny,30,a
+1,Buffon,41,b
+4,Pinsoglio,29,c
+";
+
+    fn diff() -> Result<CsvDiff> {
+        diff_csv(
+            OLD.as_bytes(),
+            NEW.as_bytes(),
+            &CsvReadOptions::default(),
+            &["id".into()],
+        )
+    }
+
+    fn render(style: DiffStyle) -> Result<String> {
+        let mut buf = Vec::new();
+        write_diff(
+            &diff()?,
+            &mut buf,
+            style,
+            &CsvReadOptions::default(),
+            ("old.csv", "new.csv"),
+        )?;
+        Ok(String::from_utf8(buf)?)
+    }
+
+    #[test]
+    fn test_diff_csv() -> Result<()> {
+        let diff = diff()?;
+        assert_eq!(diff.added_columns, ["email"]);
+        assert_eq!(diff.removed_columns, ["fax"]);
+        let kinds: Vec<_> = diff
+            .rows
+            .iter()
+            .map(|(k, c)| (k[0].1.as_str(), c.kind()))
+            .collect();
+        assert_eq!(kinds, [("3", "modified"), ("4", "added"), ("2", "removed")]);
+        match &diff.rows[0].1 {
+            RowChange::Modified { changes, .. } => assert_eq!(
+                changes,
+                &[ColumnChange {
+                    column: "age".into(),
+                    old: "29".into(),
+                    new: "30".into()
+                }]
+            ),
+            change => panic!("unexpected change {:?}", change),
+        }
+
+        let same = diff_csv(
+            OLD.as_bytes(),
+            OLD.as_bytes(),
+            &CsvReadOptions::default(),
+            &["id".into()],
+        )?;
+        assert!(same.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_styles() -> Result<()> {
+        assert_eq!(
+            render(DiffStyle::Human)?,
+            "columns added: email\ncolumns removed: fax\n~ id=3\n    age: \"29\" -> \"30\"\n+ id=4 (line 4)\n- id=2 (line 3)\n1 added, 1 removed, 1 modified\n"
+        );
+        let unified = render(DiffStyle::Unified)?;
+        assert!(unified.contains("@@ -4,1 +2,1 @@ id=3\n-3,Szczesny,29,z\n+3,Szczesny,30,a\n"));
+        assert!(unified.contains("@@ -3,1 +0,0 @@ id=2\n-2,Perin,26,y\n"));
+
+        let json: Value = serde_json::from_str(&render(DiffStyle::Json)?)?;
+        assert_eq!(
+            json["modified"],
+            json!([{"key": {"id": "3"}, "changes": {"age": {"old": "29", "new": "30"}}}])
+        );
+        assert_eq!(json["added"][0]["row"]["email"], "c");
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_duplicate_keys() {
+        let old = "id,name\n1,a\n1,b\n";
+        let ret = diff_csv(
+            old.as_bytes(),
+            old.as_bytes(),
+            &CsvReadOptions::default(),
+            &["id".into()],
+        );
+        assert!(ret.is_err());
+    }
+}
@@ -0,0 +1,194 @@
+use std::{
+    io::{self, Cursor, Read},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, Result};
+use chardetng::EncodingDetector;
+use encoding_rs::{Decoder, Encoding, UTF_8};
+
+// bytes looked at when guessing the encoding of an input
+const SNIFF_LEN: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEncoding {
+    Auto,
+    Fixed(&'static Encoding),
+}
+
+impl Default for InputEncoding {
+    fn default() -> Self {
+        InputEncoding::Fixed(UTF_8)
+    }
+}
+
+/// Wraps a reader so it yields UTF-8, returning the encoding in use along
+/// with it. With `Auto` a BOM wins, then input that is valid UTF-8, anything
+/// else is guessed from the first bytes, which covers the common legacy
+/// encodings like GBK, Shift_JIS or windows-1252. Bytes that are invalid in
+/// the chosen encoding become U+FFFD, except for UTF-8 which is passed through
+/// untouched so invalid bytes still get reported by the csv reader.
+pub fn decode_reader<'a>(
+    mut reader: impl Read + 'a,
+    encoding: InputEncoding,
+) -> Result<(Box<dyn Read + 'a>, &'static Encoding)> {
+    let (reader, encoding): (Box<dyn Read + 'a>, _) = match encoding {
+        InputEncoding::Fixed(encoding) => (Box::new(reader), encoding),
+        InputEncoding::Auto => {
+            let mut sample = Vec::new();
+            (&mut reader)
+                .take(SNIFF_LEN as u64)
+                .read_to_end(&mut sample)?;
+            let encoding = detect_encoding(&sample, sample.len() < SNIFF_LEN);
+            (Box::new(Cursor::new(sample).chain(reader)), encoding)
+        }
+    };
+    if encoding == UTF_8 {
+        return Ok((reader, encoding));
+    }
+    let decoder = encoding.new_decoder_with_bom_removal();
+    Ok((Box::new(DecodeReader::new(reader, decoder)), encoding))
+}
+
+// `complete` tells whether the sample is the whole input
+fn detect_encoding(sample: &[u8], complete: bool) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(sample) {
+        return encoding;
+    }
+    match std::str::from_utf8(sample) {
+        Ok(_) => return UTF_8,
+        // a character cut in half by the end of the sample
+        Err(e) if e.error_len().is_none() && !complete => return UTF_8,
+        Err(_) => {}
+    }
+    let mut detector = EncodingDetector::new();
+    detector.feed(sample, complete);
+    detector.guess(None, true)
+}
+
+// transcodes the inner reader to UTF-8 one chunk at a time
+struct DecodeReader<R> {
+    inner: R,
+    decoder: Decoder,
+    output: Vec<u8>,
+    pos: usize,
+    eof: bool,
+}
+
+impl<R: Read> DecodeReader<R> {
+    fn new(inner: R, decoder: Decoder) -> Self {
+        Self {
+            inner,
+            decoder,
+            output: Vec::new(),
+            pos: 0,
+            eof: false,
+        }
+    }
+
+    fn fill(&mut self) -> io::Result<()> {
+        let mut chunk = [0u8; 8 * 1024];
+        let n = self.inner.read(&mut chunk)?;
+        self.eof = n == 0;
+
+        // the decoder keeps characters split across two reads for the next call
+        let len = self
+            .decoder
+            .max_utf8_buffer_length(n)
+            .ok_or_else(|| io::Error::other("decoded chunk is too large"))?;
+        self.output.resize(len, 0);
+        let (_, _, written, _) =
+            self.decoder
+                .decode_to_utf8(&chunk[..n], &mut self.output, self.eof);
+        self.output.truncate(written);
+        self.pos = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecodeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.output.len() {
+            if self.eof {
+                return Ok(0);
+            }
+            self.fill()?;
+        }
+        let n = buf.len().min(self.output.len() - self.pos);
+        buf[..n].copy_from_slice(&self.output[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl FromStr for InputEncoding {
+    type Err = anyhow::Error;
+
+    // any WHATWG label works, e.g. utf-16le, gbk, shift_jis or latin1
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(InputEncoding::Auto);
+        }
+        Encoding::for_label(s.as_bytes())
+            .map(InputEncoding::Fixed)
+            .ok_or_else(|| anyhow!("Unknown encoding: {}", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{GBK, UTF_16LE, WINDOWS_1252};
+    use std::fs;
+
+    fn decode(input: &[u8], encoding: InputEncoding) -> Result<(String, &'static Encoding)> {
+        let (mut reader, encoding) = decode_reader(input, encoding)?;
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        Ok((text, encoding))
+    }
+
+    #[test]
+    fn test_decode_utf16_with_bom() -> Result<()> {
+        let expected = fs::read_to_string("assets/juventus.csv")?;
+        let mut input = vec![0xff, 0xfe];
+        input.extend(expected.encode_utf16().flat_map(u16::to_le_bytes));
+        for encoding in [InputEncoding::Auto, "utf-16le".parse()?] {
+            let (text, detected) = decode(&input, encoding)?;
+            assert_eq!(detected, UTF_16LE);
+            assert_eq!(text, expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_detect_legacy_encodings() -> Result<()> {
+        let chinese =
+            "姓名,城市,俱乐部\n张伟,北京,国安\n王芳,上海,申花\n李娜,广州,恒大\n".repeat(8);
+        let (input, _, _) = GBK.encode(&chinese);
+        assert_eq!(decode(&input, InputEncoding::Auto)?, (chinese, GBK));
+
+        let german = "Name,Stadt\nMüller,München\nGötze,Köln\nSchürrle,Düsseldorf\n";
+        let (input, _, _) = WINDOWS_1252.encode(german);
+        let (text, detected) = decode(&input, InputEncoding::Auto)?;
+        assert_eq!(detected, WINDOWS_1252);
+        assert_eq!(text, german);
+
+        let (text, detected) = decode(german.as_bytes(), InputEncoding::Auto)?;
+        assert_eq!((text.as_str(), detected), (german, UTF_8));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_encoding() {
+        assert_eq!(
+            "latin1".parse::<InputEncoding>().unwrap(),
+            InputEncoding::Fixed(WINDOWS_1252)
+        );
+        assert_eq!(
+            "AUTO".parse::<InputEncoding>().unwrap(),
+            InputEncoding::Auto
+        );
+        assert!("klingon".parse::<InputEncoding>().is_err());
+    }
+}
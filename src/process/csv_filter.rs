@@ -0,0 +1,437 @@
+use std::cmp::Ordering;
+
+use anyhow::{anyhow, Result};
+use csv::StringRecord;
+use regex::Regex;
+use serde_json::{Map, Value};
+
+// --where "Nationality == Italy && (Kit Number > 10 || Name =~ '^G')"
+
+/// A predicate over raw csv records, parsed from a small expression language:
+///
+/// - comparisons are `column op value`, where `op` is one of `==`, `!=`, `<`,
+///   `<=`, `>`, `>=`, `=~` (regex), `!~`, `contains`, `startswith`, `endswith`
+/// - columns and values are bare words, or quoted with `'`, `"` or backticks
+///   when they contain spaces or operator characters
+/// - comparisons combine with `&&`/`and`, `||`/`or`, `!`/`not` and parentheses
+///
+/// Ordering and equality are numeric when both sides are numbers, otherwise the
+/// text is compared as is.
+#[derive(Debug)]
+pub struct RecordFilter {
+    expr: Expr,
+}
+
+#[derive(Debug)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare {
+        column: usize,
+        op: CompareOp,
+        value: String,
+        number: Option<f64>,
+    },
+    Matches {
+        column: usize,
+        regex: Regex,
+        negate: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+    StartsWith,
+    EndsWith,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+}
+
+impl RecordFilter {
+    pub fn new(expr: &str, headers: &StringRecord) -> Result<Self> {
+        let tokens = tokenize(expr)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            headers,
+        };
+        let expr = parser.parse_or()?;
+        if let Some(token) = parser.peek() {
+            return Err(anyhow!("Unexpected {:?} in filter expression", token));
+        }
+        Ok(Self { expr })
+    }
+
+    pub fn matches(&self, record: &StringRecord) -> bool {
+        self.expr.eval(record)
+    }
+}
+
+impl Expr {
+    fn eval(&self, record: &StringRecord) -> bool {
+        match self {
+            Expr::And(l, r) => l.eval(record) && r.eval(record),
+            Expr::Or(l, r) => l.eval(record) || r.eval(record),
+            Expr::Not(e) => !e.eval(record),
+            Expr::Compare {
+                column,
+                op,
+                value,
+                number,
+            } => {
+                let field = record.get(*column).unwrap_or_default();
+                match op {
+                    CompareOp::Contains => field.contains(value.as_str()),
+                    CompareOp::StartsWith => field.starts_with(value.as_str()),
+                    CompareOp::EndsWith => field.ends_with(value.as_str()),
+                    op => {
+                        let ord = match (field.trim().parse::<f64>().ok(), number) {
+                            (Some(a), Some(b)) => a.partial_cmp(b),
+                            _ => Some(field.cmp(value.as_str())),
+                        };
+                        match (op, ord) {
+                            (CompareOp::Eq, Some(ord)) => ord == Ordering::Equal,
+                            (CompareOp::Ne, Some(ord)) => ord != Ordering::Equal,
+                            (CompareOp::Lt, Some(ord)) => ord == Ordering::Less,
+                            (CompareOp::Le, Some(ord)) => ord != Ordering::Greater,
+                            (CompareOp::Gt, Some(ord)) => ord == Ordering::Greater,
+                            (CompareOp::Ge, Some(ord)) => ord != Ordering::Less,
+                            _ => false,
+                        }
+                    }
+                }
+            }
+            Expr::Matches {
+                column,
+                regex,
+                negate,
+            } => regex.is_match(record.get(*column).unwrap_or_default()) != *negate,
+        }
+    }
+}
+
+const OPERATORS: [&str; 12] = [
+    "&&", "||", "==", "!=", "<=", ">=", "=~", "!~", "<", ">", "!", "=",
+];
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = input.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if c == '(' || c == ')' {
+            tokens.push(if c == '(' {
+                Token::LParen
+            } else {
+                Token::RParen
+            });
+            rest = &rest[1..];
+        } else if c == '"' || c == '\'' || c == '`' {
+            let end = rest[1..]
+                .find(c)
+                .ok_or_else(|| anyhow!("Unterminated quote in filter expression"))?;
+            tokens.push(Token::Quoted(rest[1..end + 1].to_string()));
+            rest = &rest[end + 2..];
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            // a single `=` is accepted as `==`
+            tokens.push(Token::Op(if *op == "=" { "==" } else { op }));
+            rest = &rest[op.len()..];
+        } else {
+            let end = rest
+                .find(|c: char| c.is_whitespace() || "()\"'`&|=!<>".contains(c))
+                .unwrap_or(rest.len());
+            if end == 0 {
+                // a lone `&` or `|`, every other stop character is handled above
+                return Err(anyhow!(
+                    "Unexpected character '{}' in filter, use {}{}",
+                    c,
+                    c,
+                    c
+                ));
+            }
+            tokens.push(Token::Word(rest[..end].to_string()));
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    headers: &'a StringRecord,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn next_is(&mut self, op: &str, keyword: &str) -> bool {
+        let found = match self.peek() {
+            Some(Token::Op(o)) => *o == op,
+            Some(Token::Word(w)) => w.eq_ignore_ascii_case(keyword),
+            _ => false,
+        };
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn parse_or(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_and()?;
+        while self.next_is("||", "or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_unary()?;
+        while self.next_is("&&", "and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr> {
+        if self.next_is("!", "not") {
+            return Ok(Expr::Not(Box::new(self.parse_unary()?)));
+        }
+        if self.peek() == Some(&Token::LParen) {
+            self.pos += 1;
+            let expr = self.parse_or()?;
+            return match self.next() {
+                Some(Token::RParen) => Ok(expr),
+                _ => Err(anyhow!("Missing closing parenthesis in filter expression")),
+            };
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr> {
+        let name = match self.next() {
+            Some(Token::Word(w)) | Some(Token::Quoted(w)) => w,
+            other => return Err(anyhow!("Expected a column name, found {:?}", other)),
+        };
+        let column = column_index(self.headers, &name)?;
+        let op = match self.next() {
+            Some(Token::Op(op)) => op.to_string(),
+            Some(Token::Word(w)) => w.to_lowercase(),
+            other => {
+                return Err(anyhow!(
+                    "Expected an operator after {:?}, found {:?}",
+                    name,
+                    other
+                ))
+            }
+        };
+        let value = match self.next() {
+            Some(Token::Word(w)) | Some(Token::Quoted(w)) => w,
+            other => {
+                return Err(anyhow!(
+                    "Expected a value after {:?}, found {:?}",
+                    op,
+                    other
+                ))
+            }
+        };
+
+        let op = match op.as_str() {
+            "=~" | "!~" => {
+                return Ok(Expr::Matches {
+                    column,
+                    regex: Regex::new(&value)?,
+                    negate: op == "!~",
+                })
+            }
+            "==" => CompareOp::Eq,
+            "!=" => CompareOp::Ne,
+            "<" => CompareOp::Lt,
+            "<=" => CompareOp::Le,
+            ">" => CompareOp::Gt,
+            ">=" => CompareOp::Ge,
+            "contains" => CompareOp::Contains,
+            "startswith" => CompareOp::StartsWith,
+            "endswith" => CompareOp::EndsWith,
+            _ => return Err(anyhow!("Unknown operator {:?}", op)),
+        };
+        Ok(Expr::Compare {
+            column,
+            op,
+            number: value.trim().parse().ok(),
+            value,
+        })
+    }
+}
+
+pub(crate) fn column_index(headers: &StringRecord, name: &str) -> Result<usize> {
+    headers.iter().position(|h| h == name).ok_or_else(|| {
+        let available = headers.iter().collect::<Vec<_>>().join(", ");
+        anyhow!(
+            "Unknown column {:?}, available columns: {}",
+            name,
+            available
+        )
+    })
+}
+
+/// Picks columns by name (or zero-based index when no column has that name)
+/// and renames them, in the order they were selected.
+#[derive(Debug)]
+pub struct Projection {
+    columns: Vec<(String, String)>,
+}
+
+impl Projection {
+    pub fn new(
+        headers: &StringRecord,
+        select: Option<&[String]>,
+        rename: &[(String, String)],
+    ) -> Result<Self> {
+        let names: Vec<String> = match select {
+            Some(select) => select
+                .iter()
+                .map(|s| match column_index(headers, s) {
+                    Ok(_) => Ok(s.clone()),
+                    Err(e) => s
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|i| headers.get(i))
+                        .map(String::from)
+                        .ok_or(e),
+                })
+                .collect::<Result<_>>()?,
+            None => headers.iter().map(String::from).collect(),
+        };
+        for (from, _) in rename {
+            column_index(headers, from)?;
+        }
+        let columns = names
+            .into_iter()
+            .map(|name| {
+                let to = rename
+                    .iter()
+                    .find(|(from, _)| *from == name)
+                    .map_or_else(|| name.clone(), |(_, to)| to.clone());
+                (name, to)
+            })
+            .collect();
+        Ok(Self { columns })
+    }
+
+    // the names of the output columns
+    pub fn names(&self) -> Vec<String> {
+        self.columns.iter().map(|(_, to)| to.clone()).collect()
+    }
+
+    pub fn apply(&self, mut record: Map<String, Value>) -> Map<String, Value> {
+        self.columns
+            .iter()
+            .map(|(from, to)| (to.clone(), record.remove(from).unwrap_or(Value::Null)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers() -> StringRecord {
+        StringRecord::from(vec!["Name", "Position", "Nationality", "Kit Number"])
+    }
+
+    fn matches(expr: &str, record: &[&str]) -> Result<bool> {
+        let filter = RecordFilter::new(expr, &headers())?;
+        Ok(filter.matches(&StringRecord::from(record.to_vec())))
+    }
+
+    #[test]
+    fn test_record_filter() -> Result<()> {
+        let buffon = ["Gianluigi Buffon", "Goalkeeper", "Italy", "77"];
+        let szczesny = ["Wojciech Szczesny", "Goalkeeper", "Poland", "1"];
+        assert!(matches("Nationality == Italy", &buffon)?);
+        assert!(!matches("Nationality == Italy", &szczesny)?);
+        assert!(matches("`Kit Number` > 10", &buffon)?);
+        assert!(matches("'Kit Number' <= 9", &szczesny)?);
+        assert!(matches("'Kit Number' == 77.0", &buffon)?);
+        assert!(matches("Name =~ '^G.*n$'", &buffon)?);
+        assert!(matches("Name !~ Buffon", &szczesny)?);
+        assert!(matches(
+            "Name contains Szcz and not Nationality = Italy",
+            &szczesny
+        )?);
+        assert!(matches(
+            "Position == Goalkeeper && (Nationality == Poland || \"Kit Number\" >= 77)",
+            &buffon
+        )?);
+        assert!(!matches("!(Position == Goalkeeper)", &buffon)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_record_filter_errors() {
+        let err = RecordFilter::new("Country == Italy", &headers()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown column \"Country\", available columns: Name, Position, Nationality, Kit Number"
+        );
+        assert!(RecordFilter::new("Name ==", &headers()).is_err());
+        assert!(RecordFilter::new("Name == x)", &headers()).is_err());
+        assert!(RecordFilter::new("(Name == x", &headers()).is_err());
+        assert!(RecordFilter::new("Name ~~ x", &headers()).is_err());
+        assert!(RecordFilter::new("Name =~ '('", &headers()).is_err());
+        let err = RecordFilter::new("Name == a & Position == b", &headers()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unexpected character '&' in filter, use &&"
+        );
+        let err = RecordFilter::new("Name == a | Position == b", &headers()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unexpected character '|' in filter, use ||"
+        );
+    }
+
+    #[test]
+    fn test_projection() -> Result<()> {
+        let select = ["Kit Number".to_string(), "0".to_string()];
+        let rename = [("Kit Number".to_string(), "number".to_string())];
+        let projection = Projection::new(&headers(), Some(&select), &rename)?;
+        let record = ["Name", "Position", "Nationality", "Kit Number"]
+            .iter()
+            .map(|h| (h.to_string(), Value::String(h.to_lowercase())))
+            .collect();
+        let ret = projection.apply(record);
+        assert_eq!(ret.keys().collect::<Vec<_>>(), ["number", "Name"]);
+        assert_eq!(ret["number"], "kit number");
+
+        let select = ["Age".to_string()];
+        assert!(Projection::new(&headers(), Some(&select), &[]).is_err());
+        let rename = [("Age".to_string(), "age".to_string())];
+        assert!(Projection::new(&headers(), None, &rename).is_err());
+        Ok(())
+    }
+}
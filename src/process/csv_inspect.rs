@@ -0,0 +1,316 @@
+use std::{collections::HashSet, fmt, io::Read, io::Write};
+
+use super::{
+    csv_output::record_writer, csv_stats::number_value, infer_type, widen_type, CoercionError,
+    ColumnType, CsvReadOptions, CsvRecords, CsvSchema, RecordTyper,
+};
+use crate::cli::OutputFormat;
+use anyhow::Result;
+use csv::{ByteRecord, StringRecord};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+#[derive(Debug, Serialize)]
+pub struct CsvReport {
+    pub rows: usize,
+    pub columns: Vec<ColumnReport>,
+    pub malformed: Vec<MalformedRow>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ColumnReport {
+    pub name: String,
+    // `None` when the column only holds empty cells
+    #[serde(rename = "type")]
+    pub ty: Option<ColumnType>,
+    pub nulls: usize,
+    pub distinct: usize,
+    pub min: Option<Value>,
+    pub max: Option<Value>,
+}
+
+/// A row that could not be read as a record of the file, e.g. a ragged row
+/// or one containing invalid UTF-8.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MalformedRow {
+    pub line: u64,
+    pub reason: String,
+}
+
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    pub rows: usize,
+    pub invalid_rows: usize,
+    pub malformed: Vec<MalformedRow>,
+    pub errors: Vec<CoercionError>,
+}
+
+#[derive(Debug, Default)]
+struct ColumnStats {
+    ty: Option<ColumnType>,
+    nulls: usize,
+    distinct: HashSet<String>,
+    min_num: Option<f64>,
+    max_num: Option<f64>,
+    min_text: Option<String>,
+    max_text: Option<String>,
+}
+
+impl ColumnStats {
+    fn add(&mut self, field: &str) {
+        let Some(ty) = infer_type(field) else {
+            self.nulls += 1;
+            return;
+        };
+        self.ty = Some(self.ty.map_or(ty, |t| widen_type(t, ty)));
+        if !self.distinct.contains(field) {
+            self.distinct.insert(field.to_string());
+        }
+        if let Ok(v) = field.parse::<f64>() {
+            self.min_num = Some(self.min_num.map_or(v, |m| m.min(v)));
+            self.max_num = Some(self.max_num.map_or(v, |m| m.max(v)));
+        }
+        if self.min_text.as_deref().is_none_or(|m| field < m) {
+            self.min_text = Some(field.to_string());
+        }
+        if self.max_text.as_deref().is_none_or(|m| field > m) {
+            self.max_text = Some(field.to_string());
+        }
+    }
+
+    fn report(self, name: &str) -> ColumnReport {
+        let (min, max) = match self.ty {
+            Some(ColumnType::Integer | ColumnType::Float) => (
+                self.min_num.map(number_value),
+                self.max_num.map(number_value),
+            ),
+            _ => (
+                self.min_text.map(Value::String),
+                self.max_text.map(Value::String),
+            ),
+        };
+        ColumnReport {
+            name: name.to_string(),
+            ty: self.ty,
+            nulls: self.nulls,
+            distinct: self.distinct.len(),
+            min,
+            max,
+        }
+    }
+}
+
+// reads records as bytes so ragged rows and invalid UTF-8 are reported
+// instead of aborting the whole read
+struct CheckedRecords<'a> {
+    reader: CsvRecords<'a>,
+    bytes: ByteRecord,
+    width: usize,
+}
+
+impl<'a> CheckedRecords<'a> {
+    fn new(reader: impl Read + 'a, read: &CsvReadOptions) -> Result<Self> {
+        let read = CsvReadOptions {
+            flexible: true,
+            ..read.clone()
+        };
+        let reader = CsvRecords::new(reader, &read)?;
+        let width = reader.headers().len();
+        Ok(Self {
+            reader,
+            bytes: ByteRecord::new(),
+            width,
+        })
+    }
+
+    fn next(&mut self, record: &mut StringRecord) -> Result<Option<Result<(), MalformedRow>>> {
+        if !self.reader.read_byte_record(&mut self.bytes)? {
+            return Ok(None);
+        }
+        let line = self.bytes.position().map_or(0, |p| p.line());
+        if self.bytes.len() != self.width {
+            return Ok(Some(Err(MalformedRow {
+                line,
+                reason: format!("expected {} fields, found {}", self.width, self.bytes.len()),
+            })));
+        }
+        match StringRecord::from_byte_record(self.bytes.clone()) {
+            Ok(r) => {
+                *record = r;
+                Ok(Some(Ok(())))
+            }
+            Err(e) => Ok(Some(Err(MalformedRow {
+                line,
+                reason: match e.utf8_error().field() {
+                    i if i < self.width => {
+                        format!("invalid UTF-8 in column {}", &self.reader.headers()[i])
+                    }
+                    _ => "invalid UTF-8".to_string(),
+                },
+            }))),
+        }
+    }
+}
+
+/// Profiles every column of a csv input: the widest inferred type, number of
+/// empty cells, distinct values and min/max. Malformed rows are collected
+/// rather than failing the read, and left out of the column statistics.
+pub fn inspect_csv(reader: impl Read, read: &CsvReadOptions) -> Result<CsvReport> {
+    let mut records = CheckedRecords::new(reader, read)?;
+    let headers = records.reader.headers().clone();
+    let mut stats: Vec<ColumnStats> = headers.iter().map(|_| ColumnStats::default()).collect();
+    let mut malformed = Vec::new();
+    let mut rows = 0;
+    let mut record = StringRecord::new();
+    while let Some(row) = records.next(&mut record)? {
+        rows += 1;
+        match row {
+            Ok(()) => {
+                for (column, field) in stats.iter_mut().zip(record.iter()) {
+                    column.add(field);
+                }
+            }
+            Err(row) => malformed.push(row),
+        }
+    }
+    Ok(CsvReport {
+        rows,
+        columns: stats
+            .into_iter()
+            .zip(headers.iter())
+            .map(|(column, name)| column.report(name))
+            .collect(),
+        malformed,
+    })
+}
+
+/// Checks every row against the schema: typed columns must parse as their
+/// type and required columns can't be empty. Errors are collected per row.
+pub fn validate_csv(
+    reader: impl Read,
+    read: &CsvReadOptions,
+    schema: &CsvSchema,
+) -> Result<ValidationReport> {
+    let mut records = CheckedRecords::new(reader, read)?;
+    let typer = RecordTyper::new(records.reader.headers(), Some(schema), false)?;
+    let mut report = ValidationReport::default();
+    let mut record = StringRecord::new();
+    while let Some(row) = records.next(&mut record)? {
+        report.rows += 1;
+        let valid = match row {
+            Ok(()) => {
+                let before = report.errors.len();
+                typer.to_value(&record, &mut report.errors);
+                report.errors.len() == before
+            }
+            Err(row) => {
+                report.malformed.push(row);
+                false
+            }
+        };
+        if !valid {
+            report.invalid_rows += 1;
+        }
+    }
+    Ok(report)
+}
+
+/// Writes the report; json, yaml and toml get the whole report while the
+/// tabular formats get one row per column.
+pub fn write_report(
+    report: &CsvReport,
+    mut writer: impl Write,
+    format: OutputFormat,
+) -> Result<()> {
+    match format {
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, report)?;
+            writeln!(writer)?;
+        }
+        OutputFormat::Yaml => serde_yaml::to_writer(&mut writer, report)?,
+        OutputFormat::Toml => write!(writer, "{}", toml::to_string(report)?)?,
+        _ => {
+            let mut writer = record_writer(writer, format, b',');
+            for column in &report.columns {
+                let mut row = Map::new();
+                row.insert("name".into(), column.name.clone().into());
+                row.insert(
+                    "type".into(),
+                    column.ty.map_or(Value::Null, |ty| ty.to_string().into()),
+                );
+                row.insert("nulls".into(), column.nulls.into());
+                row.insert("distinct".into(), column.distinct.into());
+                row.insert("min".into(), column.min.clone().unwrap_or(Value::Null));
+                row.insert("max".into(), column.max.clone().unwrap_or(Value::Null));
+                writer.write_record(&Value::Object(row))?;
+            }
+            writer.finish()?;
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for MalformedRow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_inspect_csv() -> Result<()> {
+        let mut input = b"name,age,score,joined\n\
+Buffon,41,1.5,2019-07-04\n\
+Perin,,2,2021-01-01\n\
+Szczesny,29\n"
+            .to_vec();
+        input.extend_from_slice(b"Pins\xffoglio,29,3,2020-01-01\n");
+        let report = inspect_csv(input.as_slice(), &CsvReadOptions::default())?;
+        assert_eq!(report.rows, 4);
+        assert_eq!(
+            report.malformed,
+            [
+                MalformedRow {
+                    line: 4,
+                    reason: "expected 4 fields, found 2".into()
+                },
+                MalformedRow {
+                    line: 5,
+                    reason: "invalid UTF-8 in column name".into()
+                },
+            ]
+        );
+        let age = &report.columns[1];
+        assert_eq!(age.ty, Some(ColumnType::Integer));
+        assert_eq!((age.nulls, age.distinct), (1, 1));
+        assert_eq!(age.min, Some(json!(41)));
+        let score = &report.columns[2];
+        assert_eq!(score.ty, Some(ColumnType::Float));
+        assert_eq!(
+            (score.min.clone(), score.max.clone()),
+            (Some(json!(1.5)), Some(json!(2)))
+        );
+        let joined = &report.columns[3];
+        assert_eq!(joined.ty, Some(ColumnType::Date));
+        assert_eq!(joined.max, Some(json!("2021-01-01")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_csv() -> Result<()> {
+        let schema: CsvSchema =
+            serde_yaml::from_str("{age: {type: integer, required: true}, active: boolean}")?;
+        let input =
+            "name,age,active\nBuffon,41,true\nPerin,,maybe\nSzczesny,abc\nHandanovic,35,false\n";
+        let report = validate_csv(input.as_bytes(), &CsvReadOptions::default(), &schema)?;
+        assert_eq!((report.rows, report.invalid_rows), (4, 2));
+        assert_eq!(report.errors.len(), 2);
+        assert_eq!(report.errors[0].line, 3);
+        assert_eq!(report.malformed[0].line, 4);
+        Ok(())
+    }
+}
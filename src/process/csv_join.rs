@@ -0,0 +1,237 @@
+use std::{collections::HashMap, io::Read, io::Write, str::FromStr};
+
+use super::{csv_filter::column_index, csv_output::record_writer, CsvReadOptions, CsvRecords};
+use crate::cli::OutputFormat;
+use anyhow::{anyhow, Result};
+use csv::StringRecord;
+use serde_json::{Map, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinKind {
+    Inner,
+    Left,
+    Outer,
+}
+
+/// A join key, `id` when both sides share the column name or
+/// `player_id=id` to pair a left column with a differently named right one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JoinKey {
+    pub left: String,
+    pub right: String,
+}
+
+/// Joins two csv inputs on the key columns. The right input is loaded into a
+/// hash table and the left one is streamed; rows sharing a key are paired with
+/// every match. Output columns are the left columns followed by the right
+/// ones without the keys, clashing names get a `_right` suffix.
+/// Returns the number of rows written.
+pub fn join_csv(
+    left: impl Read,
+    right: impl Read,
+    writer: impl Write,
+    read: &CsvReadOptions,
+    keys: &[JoinKey],
+    kind: JoinKind,
+    format: OutputFormat,
+) -> Result<usize> {
+    if keys.is_empty() {
+        return Err(anyhow!("At least one join key is needed"));
+    }
+    let mut left = CsvRecords::new(left, read)?;
+    let mut right = CsvRecords::new(right, read)?;
+    let left_keys = keys
+        .iter()
+        .map(|k| column_index(left.headers(), &k.left))
+        .collect::<Result<Vec<_>>>()?;
+    let right_keys = keys
+        .iter()
+        .map(|k| column_index(right.headers(), &k.right))
+        .collect::<Result<Vec<_>>>()?;
+
+    let left_names: Vec<String> = left.headers().iter().map(String::from).collect();
+    let right_columns: Vec<(usize, String)> = right
+        .headers()
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !right_keys.contains(i))
+        .map(|(i, name)| {
+            let name = if left_names.iter().any(|n| n == name) {
+                format!("{}_right", name)
+            } else {
+                name.to_string()
+            };
+            (i, name)
+        })
+        .collect();
+
+    // key -> indices into `rows`, which keeps the right side in input order
+    let mut rows = Vec::new();
+    let mut table: HashMap<Vec<String>, Vec<usize>> = HashMap::new();
+    let mut record = StringRecord::new();
+    while right.read_record(&mut record)? {
+        table
+            .entry(key_of(&record, &right_keys))
+            .or_default()
+            .push(rows.len());
+        rows.push(record.clone());
+    }
+    let mut matched = vec![false; rows.len()];
+
+    let mut writer = record_writer(writer, format, b',');
+    let mut count = 0;
+    let mut emit = |l: Option<&StringRecord>, r: Option<&StringRecord>| -> Result<()> {
+        let mut row = Map::with_capacity(left_names.len() + right_columns.len());
+        for (i, name) in left_names.iter().enumerate() {
+            // unmatched right rows still carry the key values
+            let value = match (l, left_keys.iter().position(|&k| k == i)) {
+                (Some(l), _) => l.get(i),
+                (None, Some(k)) => r.and_then(|r| r.get(right_keys[k])),
+                (None, None) => None,
+            };
+            row.insert(name.clone(), field_value(value));
+        }
+        for (i, name) in &right_columns {
+            row.insert(name.clone(), field_value(r.and_then(|r| r.get(*i))));
+        }
+        count += 1;
+        writer.write_record(&Value::Object(row))
+    };
+
+    while left.read_record(&mut record)? {
+        match table.get(&key_of(&record, &left_keys)) {
+            Some(matches) => {
+                for &i in matches {
+                    matched[i] = true;
+                    emit(Some(&record), Some(&rows[i]))?;
+                }
+            }
+            None if kind != JoinKind::Inner => emit(Some(&record), None)?,
+            None => {}
+        }
+    }
+    if kind == JoinKind::Outer {
+        for (row, _) in rows.iter().zip(&matched).filter(|(_, m)| !**m) {
+            emit(None, Some(row))?;
+        }
+    }
+    writer.finish()?;
+    Ok(count)
+}
+
+fn key_of(record: &StringRecord, columns: &[usize]) -> Vec<String> {
+    columns
+        .iter()
+        .map(|&i| record.get(i).unwrap_or_default().to_string())
+        .collect()
+}
+
+fn field_value(field: Option<&str>) -> Value {
+    field.map_or(Value::Null, |f| Value::String(f.to_string()))
+}
+
+impl FromStr for JoinKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "inner" => Ok(JoinKind::Inner),
+            "left" => Ok(JoinKind::Left),
+            "outer" | "full" => Ok(JoinKind::Outer),
+            _ => Err(anyhow!("Invalid join type: {}", s)),
+        }
+    }
+}
+
+impl FromStr for JoinKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (left, right) = s.split_once('=').unwrap_or((s, s));
+        if left.is_empty() || right.is_empty() {
+            return Err(anyhow!("Invalid join key: {:?}", s));
+        }
+        Ok(JoinKey {
+            left: left.into(),
+            right: right.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const PLAYERS: &str = "\
+id,name
+1,Buffon
+2,Perin
+3,Szczesny
+";
+    const CONTRACTS: &str = "\
+player_id,name,salary
+1,Buffon,2.5
+3,Szczesny,7
+3,Szczesny,8
+4,Pinsoglio,1
+";
+
+    fn join(kind: JoinKind) -> Result<Value> {
+        let mut buf = Vec::new();
+        join_csv(
+            PLAYERS.as_bytes(),
+            CONTRACTS.as_bytes(),
+            &mut buf,
+            &CsvReadOptions::default(),
+            &["id=player_id".parse()?],
+            kind,
+            OutputFormat::Json,
+        )?;
+        Ok(serde_json::from_slice(&buf)?)
+    }
+
+    #[test]
+    fn test_inner_join_with_duplicate_keys() -> Result<()> {
+        assert_eq!(
+            join(JoinKind::Inner)?,
+            json!([
+                {"id": "1", "name": "Buffon", "name_right": "Buffon", "salary": "2.5"},
+                {"id": "3", "name": "Szczesny", "name_right": "Szczesny", "salary": "7"},
+                {"id": "3", "name": "Szczesny", "name_right": "Szczesny", "salary": "8"},
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_left_and_outer_join() -> Result<()> {
+        let left = join(JoinKind::Left)?;
+        assert_eq!(left.as_array().unwrap().len(), 4);
+        assert_eq!(
+            left[1],
+            json!({"id": "2", "name": "Perin", "name_right": null, "salary": null})
+        );
+
+        let outer = join(JoinKind::Outer)?;
+        assert_eq!(outer.as_array().unwrap().len(), 5);
+        assert_eq!(
+            outer[4],
+            json!({"id": "4", "name": null, "name_right": "Pinsoglio", "salary": "1"})
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_join_key() {
+        assert_eq!(
+            "id".parse::<JoinKey>().unwrap(),
+            JoinKey {
+                left: "id".into(),
+                right: "id".into()
+            }
+        );
+        assert!("=id".parse::<JoinKey>().is_err());
+        assert!("cross".parse::<JoinKind>().is_err());
+    }
+}
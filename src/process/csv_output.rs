@@ -0,0 +1,513 @@
+use std::{borrow::Cow, io::Write};
+
+use super::csv_parquet::ParquetWriter;
+use crate::cli::OutputFormat;
+use anyhow::{anyhow, Result};
+use serde_json::{Map, Value};
+use unicode_width::UnicodeWidthStr;
+
+/// A sink that serializes converted records one at a time, so the whole
+/// dataset never has to be held in memory.
+pub trait RecordWriter {
+    fn write_record(&mut self, record: &Value) -> Result<()>;
+    fn finish(&mut self) -> Result<()>;
+
+    // appends `records` rendered by `render_records`, after at least one
+    // record went through write_record
+    fn write_rendered(&mut self, _rendered: &[u8], _records: usize) -> Result<()> {
+        Err(anyhow!("Records can't be rendered ahead for this format"))
+    }
+
+    // columns of the input, for formats that write a header even without records
+    fn set_columns(&mut self, _columns: &[String]) {}
+}
+
+// `delimiter` only applies to csv output
+pub fn record_writer<'a, W: Write + 'a>(
+    writer: W,
+    format: OutputFormat,
+    delimiter: u8,
+) -> Box<dyn RecordWriter + 'a> {
+    match format {
+        OutputFormat::Json => Box::new(JsonWriter::new(writer)),
+        OutputFormat::Yaml => Box::new(YamlWriter::new(writer)),
+        OutputFormat::Toml => Box::new(TomlWriter::new(writer)),
+        OutputFormat::Ndjson => Box::new(NdjsonWriter::new(writer)),
+        OutputFormat::Csv => Box::new(CsvWriter::new(writer, delimiter)),
+        OutputFormat::Table => Box::new(TableWriter::new(writer)),
+        OutputFormat::Parquet => Box::new(ParquetWriter::new(writer)),
+    }
+}
+
+/// Renders records like the writer of `format` does once the first record is
+/// out, so batches rendered on other threads can be appended in order with
+/// `write_rendered`. `columns` are the csv columns set by the first record.
+/// Returns None for table and parquet, which need every record at once.
+pub fn render_records(
+    format: OutputFormat,
+    delimiter: u8,
+    columns: &[String],
+    records: &[Value],
+) -> Result<Option<Vec<u8>>> {
+    let mut buf = Vec::new();
+    let render = |writer: &mut dyn RecordWriter| -> Result<()> {
+        for record in records {
+            writer.write_record(record)?;
+        }
+        Ok(())
+    };
+    match format {
+        OutputFormat::Json => render(&mut JsonWriter {
+            writer: &mut buf,
+            count: 1,
+        })?,
+        OutputFormat::Yaml => render(&mut YamlWriter {
+            writer: &mut buf,
+            count: 1,
+        })?,
+        OutputFormat::Toml => render(&mut TomlWriter {
+            writer: &mut buf,
+            count: 1,
+        })?,
+        OutputFormat::Ndjson => render(&mut NdjsonWriter::new(&mut buf))?,
+        OutputFormat::Csv => {
+            let mut writer = CsvWriter::new(&mut buf, delimiter);
+            writer.headers = Some(columns.to_vec());
+            render(&mut writer)?;
+            writer.finish()?;
+        }
+        OutputFormat::Table | OutputFormat::Parquet => return Ok(None),
+    }
+    Ok(Some(buf))
+}
+
+// writes a pretty printed JSON array, element by element
+pub struct JsonWriter<W: Write> {
+    writer: W,
+    count: usize,
+}
+
+impl<W: Write> JsonWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, count: 0 }
+    }
+}
+
+impl<W: Write> RecordWriter for JsonWriter<W> {
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        let sep = if self.count == 0 { "[\n" } else { ",\n" };
+        self.writer.write_all(sep.as_bytes())?;
+        let content = serde_json::to_string_pretty(record)?;
+        write_indented(&mut self.writer, &content, "  ", "  ")?;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let end = if self.count == 0 { "[]" } else { "\n]" };
+        self.writer.write_all(end.as_bytes())?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn write_rendered(&mut self, rendered: &[u8], records: usize) -> Result<()> {
+        self.writer.write_all(rendered)?;
+        self.count += records;
+        Ok(())
+    }
+}
+
+// writes a YAML sequence, emitting one `- ` entry per record
+pub struct YamlWriter<W: Write> {
+    writer: W,
+    count: usize,
+}
+
+impl<W: Write> YamlWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, count: 0 }
+    }
+}
+
+impl<W: Write> RecordWriter for YamlWriter<W> {
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        let content = serde_yaml::to_string(record)?;
+        write_indented(&mut self.writer, content.trim_end_matches('\n'), "- ", "  ")?;
+        self.writer.write_all(b"\n")?;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if self.count == 0 {
+            self.writer.write_all(b"[]\n")?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn write_rendered(&mut self, rendered: &[u8], records: usize) -> Result<()> {
+        self.writer.write_all(rendered)?;
+        self.count += records;
+        Ok(())
+    }
+}
+
+// TOML has no top level arrays, so every record becomes a `[[records]]` table
+pub struct TomlWriter<W: Write> {
+    writer: W,
+    count: usize,
+}
+
+impl<W: Write> TomlWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, count: 0 }
+    }
+}
+
+impl<W: Write> RecordWriter for TomlWriter<W> {
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        let mut doc = Map::new();
+        doc.insert("records".into(), Value::Array(vec![strip_nulls(record)]));
+        let content = toml::to_string(&doc)?;
+        if self.count > 0 {
+            self.writer.write_all(b"\n")?;
+        }
+        self.writer.write_all(content.as_bytes())?;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn write_rendered(&mut self, rendered: &[u8], records: usize) -> Result<()> {
+        self.writer.write_all(rendered)?;
+        self.count += records;
+        Ok(())
+    }
+}
+
+// TOML can't express null, so those keys are left out
+fn strip_nulls(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| (k.clone(), strip_nulls(v)))
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.iter().map(strip_nulls).collect()),
+        v => v.clone(),
+    }
+}
+
+// one compact JSON document per line
+pub struct NdjsonWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> NdjsonWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: Write> RecordWriter for NdjsonWriter<W> {
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn write_rendered(&mut self, rendered: &[u8], _records: usize) -> Result<()> {
+        self.writer.write_all(rendered)?;
+        Ok(())
+    }
+}
+
+// the columns are taken from the first record, later records are aligned to them
+pub struct CsvWriter<W: Write> {
+    // only None while write_rendered swaps the inner writer
+    writer: Option<csv::Writer<W>>,
+    delimiter: u8,
+    headers: Option<Vec<String>>,
+    // header written by finish when no record arrived
+    columns: Vec<String>,
+}
+
+impl<W: Write> CsvWriter<W> {
+    pub fn new(writer: W, delimiter: u8) -> Self {
+        Self {
+            writer: Some(csv_writer(writer, delimiter)),
+            delimiter,
+            headers: None,
+            columns: Vec::new(),
+        }
+    }
+
+    fn writer(&mut self) -> Result<&mut csv::Writer<W>> {
+        self.writer
+            .as_mut()
+            .ok_or_else(|| anyhow!("The csv writer failed earlier"))
+    }
+}
+
+fn csv_writer<W: Write>(writer: W, delimiter: u8) -> csv::Writer<W> {
+    csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(writer)
+}
+
+impl<W: Write> RecordWriter for CsvWriter<W> {
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        let map = record
+            .as_object()
+            .ok_or_else(|| anyhow!("Only objects can be written as csv rows"))?;
+        let writer = self
+            .writer
+            .as_mut()
+            .ok_or_else(|| anyhow!("The csv writer failed earlier"))?;
+        let headers = match &self.headers {
+            Some(headers) => headers,
+            None => {
+                let headers: Vec<String> = map.keys().cloned().collect();
+                writer.write_record(&headers)?;
+                self.headers.insert(headers)
+            }
+        };
+        let row = headers
+            .iter()
+            .map(|h| map.get(h).map(cell_text).unwrap_or_default());
+        writer.write_record(row.map(|c| c.into_owned()))?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if self.headers.is_none() && !self.columns.is_empty() {
+            let columns = std::mem::take(&mut self.columns);
+            self.writer()?.write_record(&columns)?;
+        }
+        self.writer()?.flush()?;
+        Ok(())
+    }
+
+    fn set_columns(&mut self, columns: &[String]) {
+        self.columns = columns.to_vec();
+    }
+
+    fn write_rendered(&mut self, rendered: &[u8], _records: usize) -> Result<()> {
+        // the csv writer only hands out what it wraps by value, after
+        // flushing what it buffered
+        let writer = self.writer.take();
+        let writer = writer.ok_or_else(|| anyhow!("The csv writer failed earlier"))?;
+        let mut inner = writer.into_inner().map_err(|e| e.into_error())?;
+        inner.write_all(rendered)?;
+        self.writer = Some(csv_writer(inner, self.delimiter));
+        Ok(())
+    }
+}
+
+// column widths depend on every row, so the table is rendered in finish()
+pub struct TableWriter<W: Write> {
+    writer: W,
+    headers: Vec<String>,
+    rows: Vec<Map<String, Value>>,
+}
+
+impl<W: Write> TableWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            headers: Vec::new(),
+            rows: Vec::new(),
+        }
+    }
+}
+
+impl<W: Write> RecordWriter for TableWriter<W> {
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        let map = record
+            .as_object()
+            .ok_or_else(|| anyhow!("Only objects can be written as table rows"))?;
+        for key in map.keys() {
+            if !self.headers.contains(key) {
+                self.headers.push(key.clone());
+            }
+        }
+        self.rows.push(map.clone());
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if self.headers.is_empty() {
+            return Ok(());
+        }
+        let cells: Vec<Vec<String>> = self
+            .rows
+            .iter()
+            .map(|row| {
+                self.headers
+                    .iter()
+                    .map(|h| {
+                        row.get(h)
+                            .map(cell_text)
+                            .unwrap_or_default()
+                            .replace('\n', " ")
+                    })
+                    .collect()
+            })
+            .collect();
+        let widths: Vec<usize> = self
+            .headers
+            .iter()
+            .enumerate()
+            .map(|(i, h)| {
+                cells
+                    .iter()
+                    .map(|row| row[i].width())
+                    .fold(h.width(), usize::max)
+            })
+            .collect();
+
+        let border = widths.iter().fold(String::from("+"), |mut acc, w| {
+            acc.push_str(&"-".repeat(w + 2));
+            acc.push('+');
+            acc
+        });
+        writeln!(self.writer, "{}", border)?;
+        write_table_row(&mut self.writer, &self.headers, &widths)?;
+        writeln!(self.writer, "{}", border)?;
+        for row in &cells {
+            write_table_row(&mut self.writer, row, &widths)?;
+        }
+        if !cells.is_empty() {
+            writeln!(self.writer, "{}", border)?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+fn write_table_row(writer: &mut impl Write, row: &[String], widths: &[usize]) -> Result<()> {
+    write!(writer, "|")?;
+    for (cell, width) in row.iter().zip(widths) {
+        write!(writer, " {}{} |", cell, " ".repeat(width - cell.width()))?;
+    }
+    writeln!(writer)?;
+    Ok(())
+}
+
+// text of a single value when it has to fit a flat cell
+pub fn cell_text(value: &Value) -> Cow<'_, str> {
+    match value {
+        Value::Null => Cow::Borrowed(""),
+        Value::String(s) => Cow::Borrowed(s),
+        v => Cow::Owned(v.to_string()),
+    }
+}
+
+fn write_indented(writer: &mut impl Write, content: &str, first: &str, rest: &str) -> Result<()> {
+    for (i, line) in content.split('\n').enumerate() {
+        if i > 0 {
+            writer.write_all(b"\n")?;
+        }
+        // keep blank lines blank, like serde does for nested block scalars
+        if !line.is_empty() {
+            writer.write_all(if i == 0 { first } else { rest }.as_bytes())?;
+        }
+        writer.write_all(line.as_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn render(format: OutputFormat, records: &[Value]) -> Result<String> {
+        let mut buf = Vec::new();
+        let mut writer = record_writer(&mut buf, format, b',');
+        for record in records {
+            writer.write_record(record)?;
+        }
+        writer.finish()?;
+        drop(writer);
+        Ok(String::from_utf8(buf)?)
+    }
+
+    #[test]
+    fn test_streamed_output_matches_buffered() -> Result<()> {
+        let records = vec![
+            json!({"Name": "Gianluigi Buffon", "Kit Number": "77"}),
+            json!({"Name": "Mattia Perin", "Kit Number": "37"}),
+        ];
+        assert_eq!(
+            render(OutputFormat::Json, &records)?,
+            serde_json::to_string_pretty(&records)?
+        );
+        assert_eq!(
+            render(OutputFormat::Yaml, &records)?,
+            serde_yaml::to_string(&records)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_streamed_output_empty() -> Result<()> {
+        assert_eq!(render(OutputFormat::Json, &[])?, "[]");
+        assert_eq!(render(OutputFormat::Yaml, &[])?, "[]\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_other_formats() -> Result<()> {
+        let records = vec![
+            json!({"Name": "Buffon, Gianluigi", "Kit Number": 77, "Retired": null}),
+            json!({"Name": "Perin", "Kit Number": 37, "Retired": false}),
+        ];
+        assert_eq!(
+            render(OutputFormat::Ndjson, &records)?,
+            "{\"Name\":\"Buffon, Gianluigi\",\"Kit Number\":77,\"Retired\":null}\n\
+             {\"Name\":\"Perin\",\"Kit Number\":37,\"Retired\":false}\n"
+        );
+        assert_eq!(
+            render(OutputFormat::Csv, &records)?,
+            "Name,Kit Number,Retired\n\"Buffon, Gianluigi\",77,\nPerin,37,false\n"
+        );
+        assert_eq!(
+            render(OutputFormat::Toml, &records)?,
+            "[[records]]\nName = \"Buffon, Gianluigi\"\n\"Kit Number\" = 77\n\n\
+             [[records]]\nName = \"Perin\"\n\"Kit Number\" = 37\nRetired = false\n"
+        );
+        assert_eq!(
+            render(OutputFormat::Table, &records)?,
+            "+-------------------+------------+---------+\n\
+             | Name              | Kit Number | Retired |\n\
+             +-------------------+------------+---------+\n\
+             | Buffon, Gianluigi | 77         |         |\n\
+             | Perin             | 37         | false   |\n\
+             +-------------------+------------+---------+\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv_output_delimiter() -> Result<()> {
+        let mut buf = Vec::new();
+        let mut writer = record_writer(&mut buf, OutputFormat::Csv, b';');
+        writer.write_record(&json!({"a": "x;y", "b": 1}))?;
+        writer.finish()?;
+        drop(writer);
+        assert_eq!(String::from_utf8(buf)?, "a;b\n\"x;y\";1\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,294 @@
+use std::{io::Write, sync::Arc};
+
+use super::{
+    csv_output::{cell_text, RecordWriter},
+    csv_schema::{parse_date, parse_datetime},
+    infer_type, widen_type, ColumnType,
+};
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use parquet::{
+    basic::{LogicalType, Repetition, TimeUnit, Type as PhysicalType},
+    data_type::{BoolType, ByteArray, ByteArrayType, DoubleType, Int32Type, Int64Type},
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    format::MicroSeconds,
+    schema::types::Type,
+};
+use serde_json::{Map, Value};
+
+/// Writes records as a single parquet row group. Parquet needs the schema
+/// upfront, so records are buffered and every column gets the widest type
+/// inferred from its values; empty cells become nulls.
+pub struct ParquetWriter<W: Write> {
+    writer: W,
+    headers: Vec<String>,
+    rows: Vec<Map<String, Value>>,
+}
+
+impl<W: Write> ParquetWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            headers: Vec::new(),
+            rows: Vec::new(),
+        }
+    }
+
+    fn column_type(&self, name: &str) -> ColumnType {
+        self.rows
+            .iter()
+            .filter_map(|row| value_type(row.get(name)?))
+            .reduce(widen_type)
+            .unwrap_or(ColumnType::String)
+    }
+}
+
+impl<W: Write> RecordWriter for ParquetWriter<W> {
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        let map = record
+            .as_object()
+            .ok_or_else(|| anyhow!("Only objects can be written as parquet rows"))?;
+        for key in map.keys() {
+            if !self.headers.contains(key) {
+                self.headers.push(key.clone());
+            }
+        }
+        self.rows.push(map.clone());
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let types: Vec<ColumnType> = self.headers.iter().map(|h| self.column_type(h)).collect();
+        let fields = self
+            .headers
+            .iter()
+            .zip(&types)
+            .map(|(name, ty)| parquet_field(name, *ty).map(Arc::new))
+            .collect::<Result<Vec<_>>>()?;
+        let schema = Type::group_type_builder("schema")
+            .with_fields(fields)
+            .build()?;
+
+        // the parquet writer needs a `Send` sink, so the file is built in memory
+        let mut buf = Vec::new();
+        let props = Arc::new(WriterProperties::builder().build());
+        let mut writer = SerializedFileWriter::new(&mut buf, Arc::new(schema), props)?;
+        let mut row_group = writer.next_row_group()?;
+        for (name, ty) in self.headers.iter().zip(&types) {
+            let mut column = row_group
+                .next_column()?
+                .ok_or_else(|| anyhow!("Parquet schema has fewer columns than the records"))?;
+            let values = self.rows.iter().map(|row| row.get(name));
+            let levels = values
+                .clone()
+                .map(|v| i16::from(!is_empty(v)))
+                .collect::<Vec<_>>();
+            let present = values.filter(|v| !is_empty(*v)).flatten();
+            match ty {
+                ColumnType::Integer => {
+                    let data = present.map(to_integer).collect::<Result<Vec<_>>>()?;
+                    column
+                        .typed::<Int64Type>()
+                        .write_batch(&data, Some(&levels), None)?;
+                }
+                ColumnType::Float => {
+                    let data = present.map(to_float).collect::<Result<Vec<_>>>()?;
+                    column
+                        .typed::<DoubleType>()
+                        .write_batch(&data, Some(&levels), None)?;
+                }
+                ColumnType::Boolean => {
+                    let data: Vec<bool> = present.map(to_bool).collect();
+                    column
+                        .typed::<BoolType>()
+                        .write_batch(&data, Some(&levels), None)?;
+                }
+                ColumnType::Date => {
+                    let epoch = NaiveDate::default();
+                    let data = present
+                        .map(|v| {
+                            let date = parse_date(&cell_text(v))
+                                .ok_or_else(|| anyhow!("Invalid date: {}", v))?;
+                            Ok((date - epoch).num_days() as i32)
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+                    column
+                        .typed::<Int32Type>()
+                        .write_batch(&data, Some(&levels), None)?;
+                }
+                ColumnType::Datetime => {
+                    let data = present
+                        .map(|v| {
+                            // dates in a datetime column are taken as midnight
+                            let text = cell_text(v);
+                            let dt = parse_datetime(&text)
+                                .or_else(|| parse_date(&text)?.and_hms_opt(0, 0, 0))
+                                .ok_or_else(|| anyhow!("Invalid datetime: {}", v))?;
+                            Ok(dt.and_utc().timestamp_micros())
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+                    column
+                        .typed::<Int64Type>()
+                        .write_batch(&data, Some(&levels), None)?;
+                }
+                ColumnType::String => {
+                    let data: Vec<ByteArray> =
+                        present.map(|v| cell_text(v).as_bytes().into()).collect();
+                    column
+                        .typed::<ByteArrayType>()
+                        .write_batch(&data, Some(&levels), None)?;
+                }
+            }
+            column.close()?;
+        }
+        row_group.close()?;
+        writer.close()?;
+        self.writer.write_all(&buf)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+fn parquet_field(name: &str, ty: ColumnType) -> Result<Type> {
+    let (physical, logical) = match ty {
+        ColumnType::String => (PhysicalType::BYTE_ARRAY, Some(LogicalType::String)),
+        ColumnType::Integer => (PhysicalType::INT64, None),
+        ColumnType::Float => (PhysicalType::DOUBLE, None),
+        ColumnType::Boolean => (PhysicalType::BOOLEAN, None),
+        ColumnType::Date => (PhysicalType::INT32, Some(LogicalType::Date)),
+        ColumnType::Datetime => (
+            PhysicalType::INT64,
+            Some(LogicalType::Timestamp {
+                is_adjusted_to_u_t_c: true,
+                unit: TimeUnit::MICROS(MicroSeconds {}),
+            }),
+        ),
+    };
+    Ok(Type::primitive_type_builder(name, physical)
+        .with_repetition(Repetition::OPTIONAL)
+        .with_logical_type(logical)
+        .build()?)
+}
+
+// typed values keep their type, strings are inferred like csv cells
+fn value_type(value: &Value) -> Option<ColumnType> {
+    match value {
+        Value::Null => None,
+        Value::Bool(_) => Some(ColumnType::Boolean),
+        Value::Number(n) if n.is_i64() => Some(ColumnType::Integer),
+        Value::Number(_) => Some(ColumnType::Float),
+        Value::String(s) => infer_type(s),
+        _ => Some(ColumnType::String),
+    }
+}
+
+fn is_empty(value: Option<&Value>) -> bool {
+    match value {
+        None | Some(Value::Null) => true,
+        Some(Value::String(s)) => s.is_empty(),
+        _ => false,
+    }
+}
+
+fn to_bool(value: &Value) -> bool {
+    match value {
+        Value::Bool(b) => *b,
+        Value::String(s) => s.eq_ignore_ascii_case("true"),
+        _ => false,
+    }
+}
+
+fn to_integer(value: &Value) -> Result<i64> {
+    match value {
+        Value::Number(n) => n.as_i64(),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| anyhow!("Invalid integer: {}", value))
+}
+
+fn to_float(value: &Value) -> Result<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| anyhow!("Invalid float: {}", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cli::OutputFormat, convert_csv, CsvConvertOptions};
+    use parquet::{
+        file::reader::{FileReader, SerializedFileReader},
+        record::Field,
+    };
+    use std::{
+        fs::File,
+        io::{Read, Seek},
+    };
+
+    fn to_parquet(input: impl Read) -> Result<(usize, SerializedFileReader<File>)> {
+        let mut file = tempfile::tempfile()?;
+        let count = convert_csv(
+            input,
+            &mut file,
+            OutputFormat::Parquet,
+            &CsvConvertOptions::default(),
+        )?;
+        file.rewind()?;
+        Ok((count, SerializedFileReader::new(file)?))
+    }
+
+    #[test]
+    fn test_parquet_round_trip() -> Result<()> {
+        let (count, reader) = to_parquet(File::open("assets/juventus.csv")?)?;
+        let schema = reader.metadata().file_metadata().schema_descr();
+        assert_eq!(schema.num_columns(), 5);
+        assert_eq!(schema.column(0).physical_type(), PhysicalType::BYTE_ARRAY);
+        assert_eq!(schema.column(4).physical_type(), PhysicalType::INT64);
+
+        let mut csv = csv::Reader::from_path("assets/juventus.csv")?;
+        let mut rows = 0;
+        for (row, record) in reader.get_row_iter(None)?.zip(csv.records()) {
+            let (row, record) = (row?, record?);
+            for ((name, field), cell) in row.get_column_iter().zip(record.iter()) {
+                let value = match field {
+                    Field::Str(s) => s.clone(),
+                    Field::Long(n) => n.to_string(),
+                    Field::Null => String::new(),
+                    _ => panic!("unexpected {} value: {:?}", name, field),
+                };
+                assert_eq!(value, cell);
+            }
+            rows += 1;
+        }
+        assert_eq!(rows, count);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parquet_column_types() -> Result<()> {
+        let input =
+            "id,score,joined,seen,note\n1,1.5,2019-07-04,2019-07-04T10:00:00Z,\n2,,2021-01-01,,x\n";
+        let (_, reader) = to_parquet(input.as_bytes())?;
+        let schema = reader.metadata().file_metadata().schema_descr();
+        let types: Vec<_> = schema
+            .columns()
+            .iter()
+            .map(|c| (c.physical_type(), c.logical_type()))
+            .collect();
+        assert_eq!(types[1], (PhysicalType::DOUBLE, None));
+        assert_eq!(types[2], (PhysicalType::INT32, Some(LogicalType::Date)));
+        assert_eq!(types[3].0, PhysicalType::INT64);
+
+        let rows: Vec<_> = reader.get_row_iter(None)?.collect::<Result<_, _>>()?;
+        let second: Vec<_> = rows[1].get_column_iter().map(|(_, f)| f.clone()).collect();
+        assert_eq!(second[0], Field::Long(2));
+        assert_eq!(second[1], Field::Null);
+        assert_eq!(second[2], Field::Date(18628));
+        assert_eq!(second[3], Field::Null);
+        Ok(())
+    }
+}
@@ -0,0 +1,1450 @@
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    fmt,
+    io::{Read, Write},
+    str::FromStr,
+};
+
+use super::{
+    csv_output::record_writer, csv_stats::number_value, infer_value, AggregateFn, CsvReadOptions,
+    CsvRecords, JoinKind,
+};
+use crate::cli::OutputFormat;
+use anyhow::{anyhow, Result};
+use csv::StringRecord;
+use serde_json::{Map, Number, Value};
+
+// SELECT [DISTINCT] items FROM t [[INNER|LEFT] JOIN u ON expr]... [WHERE expr]
+//   [GROUP BY expr, ...] [HAVING expr] [ORDER BY expr [ASC|DESC], ...] [LIMIT n [OFFSET m]]
+
+const KEYWORDS: [&str; 27] = [
+    "SELECT", "DISTINCT", "FROM", "WHERE", "GROUP", "BY", "HAVING", "ORDER", "LIMIT", "OFFSET",
+    "JOIN", "INNER", "LEFT", "OUTER", "ON", "AS", "AND", "OR", "NOT", "ASC", "DESC", "IS", "NULL",
+    "IN", "LIKE", "BETWEEN", "CROSS",
+];
+
+/// A csv file loaded as a table, cells are typed like `--infer` does.
+#[derive(Debug, Clone, Default)]
+pub struct CsvTable {
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+impl CsvTable {
+    pub fn load(reader: impl Read, read: &CsvReadOptions) -> Result<Self> {
+        let mut reader = CsvRecords::new(reader, read)?;
+        let headers = reader.headers().iter().map(String::from).collect();
+        let mut rows = Vec::new();
+        let mut record = StringRecord::new();
+        while reader.read_record(&mut record)? {
+            rows.push(record.iter().map(infer_value).collect());
+        }
+        Ok(Self { headers, rows })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    // a "quoted", `quoted` or [quoted] identifier, never a keyword
+    Ident(String),
+    Str(String),
+    Num(String),
+    Sym(&'static str),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Literal(Value),
+    Column {
+        table: Option<String>,
+        name: String,
+    },
+    // a column resolved to its position in the joined row
+    Bound(usize),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    IsNull {
+        expr: Box<Expr>,
+        negated: bool,
+    },
+    InList {
+        expr: Box<Expr>,
+        list: Vec<Expr>,
+        negated: bool,
+    },
+    Like {
+        expr: Box<Expr>,
+        pattern: Box<Expr>,
+        negated: bool,
+    },
+    // `arg` is `None` for COUNT(*)
+    Aggregate {
+        func: AggregateFn,
+        arg: Option<Box<Expr>>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum SelectItem {
+    // `*` or `t.*`
+    Wildcard(Option<String>),
+    Expr { expr: Expr, alias: Option<String> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct TableRef {
+    name: String,
+    alias: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Join {
+    kind: JoinKind,
+    table: TableRef,
+    on: Option<Expr>,
+}
+
+/// A parsed `SELECT` statement over csv tables.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SqlQuery {
+    distinct: bool,
+    items: Vec<SelectItem>,
+    from: TableRef,
+    joins: Vec<Join>,
+    filter: Option<Expr>,
+    group_by: Vec<Expr>,
+    having: Option<Expr>,
+    order_by: Vec<(Expr, bool)>,
+    limit: Option<usize>,
+    offset: usize,
+}
+
+impl SqlQuery {
+    /// Names of the tables the query reads from, in order of appearance.
+    pub fn tables(&self) -> Vec<&str> {
+        let mut names = vec![self.from.name.as_str()];
+        for join in &self.joins {
+            if !names.contains(&join.table.name.as_str()) {
+                names.push(&join.table.name);
+            }
+        }
+        names
+    }
+
+    /// Runs the query and writes the result rows, returns the number of rows written.
+    pub fn execute(
+        &self,
+        tables: &HashMap<String, CsvTable>,
+        writer: impl Write,
+        format: OutputFormat,
+    ) -> Result<usize> {
+        let table = |t: &TableRef| {
+            tables
+                .get(&t.name)
+                .ok_or_else(|| anyhow!("Unknown table: {}", t.name))
+        };
+        let first = table(&self.from)?;
+        let mut schema: Vec<(String, String)> = first
+            .headers
+            .iter()
+            .map(|h| (self.from.alias.clone(), h.clone()))
+            .collect();
+        let mut rows = first.rows.clone();
+        for join in &self.joins {
+            let right = table(&join.table)?;
+            let mut joined_schema = schema.clone();
+            joined_schema.extend(
+                right
+                    .headers
+                    .iter()
+                    .map(|h| (join.table.alias.clone(), h.clone())),
+            );
+            let on = join
+                .on
+                .as_ref()
+                .map(|on| bind(on, &joined_schema, false))
+                .transpose()?;
+            rows = join_rows(
+                rows,
+                &right.rows,
+                (schema.len(), right.headers.len()),
+                on.as_ref(),
+                join.kind,
+            )?;
+            schema = joined_schema;
+        }
+
+        if let Some(filter) = &self.filter {
+            let filter = bind(filter, &schema, false)?;
+            let mut kept = Vec::with_capacity(rows.len());
+            for row in rows {
+                if truth(&eval(&filter, &[&row])?) == Some(true) {
+                    kept.push(row);
+                }
+            }
+            rows = kept;
+        }
+
+        // expand the select list into output columns
+        let mut names = Vec::new();
+        let mut exprs = Vec::new();
+        for item in &self.items {
+            match item {
+                SelectItem::Wildcard(table) => {
+                    let mut found = false;
+                    for (i, (alias, name)) in schema.iter().enumerate() {
+                        if table.as_ref().is_some_and(|t| t != alias) {
+                            continue;
+                        }
+                        found = true;
+                        let shared = schema.iter().filter(|(_, n)| n == name).count() > 1;
+                        names.push(if shared {
+                            format!("{}.{}", alias, name)
+                        } else {
+                            name.clone()
+                        });
+                        exprs.push(Expr::Bound(i));
+                    }
+                    if !found {
+                        return Err(anyhow!("Unknown table: {}", table.as_deref().unwrap_or("")));
+                    }
+                }
+                SelectItem::Expr { expr, alias } => {
+                    names.push(match (alias, expr) {
+                        (Some(alias), _) => alias.clone(),
+                        (None, Expr::Column { name, .. }) => name.clone(),
+                        (None, expr) => expr.to_string(),
+                    });
+                    exprs.push(bind(expr, &schema, true)?);
+                }
+            }
+        }
+        dedup_names(&mut names);
+
+        // order keys are output columns (by name or 1-based position) or expressions
+        enum SortKey {
+            Output(usize),
+            Expr(Expr),
+        }
+        let sort_keys = self
+            .order_by
+            .iter()
+            .map(|(expr, _)| match expr {
+                Expr::Column { table: None, name } if names.contains(name) => Ok(SortKey::Output(
+                    names.iter().position(|n| n == name).unwrap(),
+                )),
+                Expr::Literal(Value::Number(n)) => match n.as_u64() {
+                    Some(i) if i >= 1 && (i as usize) <= names.len() => {
+                        Ok(SortKey::Output(i as usize - 1))
+                    }
+                    _ => Err(anyhow!("ORDER BY position {} is out of range", n)),
+                },
+                expr => Ok(SortKey::Expr(bind(expr, &schema, true)?)),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let grouped = !self.group_by.is_empty()
+            || exprs.iter().any(has_aggregate)
+            || self.having.is_some()
+            || sort_keys
+                .iter()
+                .any(|k| matches!(k, SortKey::Expr(e) if has_aggregate(e)));
+        let groups: Vec<Vec<&Vec<Value>>> = if grouped {
+            let group_by = self
+                .group_by
+                .iter()
+                .map(|e| bind(e, &schema, false))
+                .collect::<Result<Vec<_>>>()?;
+            let mut groups: Vec<Vec<&Vec<Value>>> = Vec::new();
+            let mut index = HashMap::new();
+            for row in &rows {
+                let key = group_by
+                    .iter()
+                    .map(|e| eval(e, &[row]))
+                    .collect::<Result<Vec<_>>>()?;
+                let i = *index
+                    .entry(Value::Array(key).to_string())
+                    .or_insert_with(|| {
+                        groups.push(Vec::new());
+                        groups.len() - 1
+                    });
+                groups[i].push(row);
+            }
+            // aggregates without GROUP BY always produce a row
+            if groups.is_empty() && self.group_by.is_empty() {
+                groups.push(Vec::new());
+            }
+            if let Some(having) = &self.having {
+                let having = bind(having, &schema, true)?;
+                let mut kept = Vec::with_capacity(groups.len());
+                for group in groups {
+                    if truth(&eval(&having, &group)?) == Some(true) {
+                        kept.push(group);
+                    }
+                }
+                groups = kept;
+            }
+            groups
+        } else {
+            rows.iter().map(|row| vec![row]).collect()
+        };
+
+        let mut output = Vec::with_capacity(groups.len());
+        for group in &groups {
+            let values = exprs
+                .iter()
+                .map(|e| eval(e, group))
+                .collect::<Result<Vec<_>>>()?;
+            let keys = sort_keys
+                .iter()
+                .map(|k| match k {
+                    SortKey::Output(i) => Ok(values[*i].clone()),
+                    SortKey::Expr(e) => eval(e, group),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            output.push((values, keys));
+        }
+        if self.distinct {
+            let mut seen = HashSet::new();
+            output.retain(|(values, _)| seen.insert(Value::Array(values.clone()).to_string()));
+        }
+        if !self.order_by.is_empty() {
+            output.sort_by(|(_, a), (_, b)| {
+                a.iter()
+                    .zip(b)
+                    .zip(&self.order_by)
+                    .map(|((a, b), (_, desc))| {
+                        let ord = sort_order(a, b);
+                        if *desc {
+                            ord.reverse()
+                        } else {
+                            ord
+                        }
+                    })
+                    .find(|ord| ord.is_ne())
+                    .unwrap_or(Ordering::Equal)
+            });
+        }
+
+        let mut writer = record_writer(writer, format, b',');
+        let mut count = 0;
+        for (values, _) in output
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+        {
+            let row: Map<String, Value> = names.iter().cloned().zip(values).collect();
+            writer.write_record(&Value::Object(row))?;
+            count += 1;
+        }
+        writer.finish()?;
+        Ok(count)
+    }
+}
+
+// equality joins on a column of each side use a hash table, anything else a nested loop
+// `widths` are the number of columns on each side, the right side may have no rows
+fn join_rows(
+    left: Vec<Vec<Value>>,
+    right: &[Vec<Value>],
+    (width, right_width): (usize, usize),
+    on: Option<&Expr>,
+    kind: JoinKind,
+) -> Result<Vec<Vec<Value>>> {
+    let mut ret = Vec::new();
+    let mut push = |l: &[Value], r: Option<&Vec<Value>>| {
+        let mut row = l.to_vec();
+        match r {
+            Some(r) => row.extend_from_slice(r),
+            None => row.resize(width + right_width, Value::Null),
+        }
+        ret.push(row);
+    };
+
+    let equi = match on {
+        Some(Expr::Binary {
+            op: BinaryOp::Eq,
+            left: a,
+            right: b,
+        }) => match (a.as_ref(), b.as_ref()) {
+            (Expr::Bound(a), Expr::Bound(b)) if *a < width && *b >= width => Some((*a, *b - width)),
+            (Expr::Bound(a), Expr::Bound(b)) if *b < width && *a >= width => Some((*b, *a - width)),
+            _ => None,
+        },
+        _ => None,
+    };
+    if let Some((l, r)) = equi {
+        let mut table: HashMap<String, Vec<&Vec<Value>>> = HashMap::new();
+        for row in right.iter().filter(|row| !row[r].is_null()) {
+            table.entry(join_key(&row[r])).or_default().push(row);
+        }
+        for row in &left {
+            match table.get(&join_key(&row[l])).filter(|_| !row[l].is_null()) {
+                Some(matches) => matches.iter().for_each(|m| push(row, Some(m))),
+                None if kind == JoinKind::Left => push(row, None),
+                None => {}
+            }
+        }
+        return Ok(ret);
+    }
+
+    for row in &left {
+        let mut matched = false;
+        for other in right {
+            let ok = match on {
+                Some(on) => {
+                    let joined: Vec<Value> = row.iter().chain(other).cloned().collect();
+                    truth(&eval(on, &[&joined])?) == Some(true)
+                }
+                None => true,
+            };
+            if ok {
+                matched = true;
+                push(row, Some(other));
+            }
+        }
+        if !matched && kind == JoinKind::Left {
+            push(row, None);
+        }
+    }
+    Ok(ret)
+}
+
+// numbers equal in value join together regardless of how they were written
+fn join_key(value: &Value) -> String {
+    match value {
+        Value::Number(n) => n.as_f64().map_or(n.to_string(), |f| f.to_string()),
+        v => v.to_string(),
+    }
+}
+
+// repeated output names get a numeric suffix, like `name_2`
+fn dedup_names(names: &mut [String]) {
+    let mut seen = HashSet::new();
+    for name in names.iter_mut() {
+        let mut candidate = name.clone();
+        let mut n = 2;
+        while !seen.insert(candidate.clone()) {
+            candidate = format!("{}_{}", name, n);
+            n += 1;
+        }
+        *name = candidate;
+    }
+}
+
+// resolves column references against the joined row layout
+fn bind(expr: &Expr, schema: &[(String, String)], allow_aggregate: bool) -> Result<Expr> {
+    let bind_box = |e: &Expr| bind(e, schema, allow_aggregate).map(Box::new);
+    Ok(match expr {
+        Expr::Column { table, name } => {
+            let matches: Vec<usize> = schema
+                .iter()
+                .enumerate()
+                .filter(|(_, (t, n))| n == name && table.as_ref().is_none_or(|table| table == t))
+                .map(|(i, _)| i)
+                .collect();
+            match matches.as_slice() {
+                [i] => Expr::Bound(*i),
+                [] => {
+                    let available: Vec<&str> = schema.iter().map(|(_, n)| n.as_str()).collect();
+                    return Err(anyhow!(
+                        "Unknown column {:?}, available columns: {}",
+                        expr.to_string(),
+                        available.join(", ")
+                    ));
+                }
+                _ => {
+                    return Err(anyhow!(
+                        "Column {:?} is ambiguous, qualify it with a table name",
+                        name
+                    ))
+                }
+            }
+        }
+        Expr::Literal(_) | Expr::Bound(_) => expr.clone(),
+        Expr::Not(e) => Expr::Not(bind_box(e)?),
+        Expr::Neg(e) => Expr::Neg(bind_box(e)?),
+        Expr::Binary { op, left, right } => Expr::Binary {
+            op: *op,
+            left: bind_box(left)?,
+            right: bind_box(right)?,
+        },
+        Expr::IsNull { expr, negated } => Expr::IsNull {
+            expr: bind_box(expr)?,
+            negated: *negated,
+        },
+        Expr::InList {
+            expr,
+            list,
+            negated,
+        } => Expr::InList {
+            expr: bind_box(expr)?,
+            list: list
+                .iter()
+                .map(|e| bind(e, schema, allow_aggregate))
+                .collect::<Result<_>>()?,
+            negated: *negated,
+        },
+        Expr::Like {
+            expr,
+            pattern,
+            negated,
+        } => Expr::Like {
+            expr: bind_box(expr)?,
+            pattern: bind_box(pattern)?,
+            negated: *negated,
+        },
+        Expr::Aggregate { func, arg } => {
+            if !allow_aggregate {
+                return Err(anyhow!(
+                    "Aggregate {} is not allowed here",
+                    expr.to_string()
+                ));
+            }
+            Expr::Aggregate {
+                func: *func,
+                arg: arg
+                    .as_ref()
+                    .map(|e| bind(e, schema, false).map(Box::new))
+                    .transpose()?,
+            }
+        }
+    })
+}
+
+fn has_aggregate(expr: &Expr) -> bool {
+    match expr {
+        Expr::Aggregate { .. } => true,
+        Expr::Literal(_) | Expr::Column { .. } | Expr::Bound(_) => false,
+        Expr::Not(e) | Expr::Neg(e) | Expr::IsNull { expr: e, .. } => has_aggregate(e),
+        Expr::Binary { left, right, .. } => has_aggregate(left) || has_aggregate(right),
+        Expr::InList { expr, list, .. } => has_aggregate(expr) || list.iter().any(has_aggregate),
+        Expr::Like { expr, pattern, .. } => has_aggregate(expr) || has_aggregate(pattern),
+    }
+}
+
+// evaluates a bound expression over a group of rows; plain columns read the
+// first row, which is the row itself outside of aggregate queries
+fn eval(expr: &Expr, group: &[&Vec<Value>]) -> Result<Value> {
+    Ok(match expr {
+        Expr::Literal(v) => v.clone(),
+        Expr::Bound(i) => group.first().map_or(Value::Null, |row| row[*i].clone()),
+        Expr::Column { name, .. } => return Err(anyhow!("Unresolved column {:?}", name)),
+        Expr::Not(e) => truth(&eval(e, group)?).map_or(Value::Null, |b| Value::Bool(!b)),
+        Expr::Neg(e) => match eval(e, group)? {
+            Value::Null => Value::Null,
+            v => arithmetic(BinaryOp::Sub, &Value::from(0), &v),
+        },
+        Expr::Binary { op, left, right } => {
+            let (l, r) = (eval(left, group)?, eval(right, group)?);
+            match op {
+                BinaryOp::And => match (truth(&l), truth(&r)) {
+                    (Some(false), _) | (_, Some(false)) => Value::Bool(false),
+                    (Some(true), Some(true)) => Value::Bool(true),
+                    _ => Value::Null,
+                },
+                BinaryOp::Or => match (truth(&l), truth(&r)) {
+                    (Some(true), _) | (_, Some(true)) => Value::Bool(true),
+                    (Some(false), Some(false)) => Value::Bool(false),
+                    _ => Value::Null,
+                },
+                BinaryOp::Eq
+                | BinaryOp::Ne
+                | BinaryOp::Lt
+                | BinaryOp::Le
+                | BinaryOp::Gt
+                | BinaryOp::Ge => match compare(&l, &r) {
+                    None => Value::Null,
+                    Some(ord) => Value::Bool(match op {
+                        BinaryOp::Eq => ord.is_eq(),
+                        BinaryOp::Ne => ord.is_ne(),
+                        BinaryOp::Lt => ord.is_lt(),
+                        BinaryOp::Le => ord.is_le(),
+                        BinaryOp::Gt => ord.is_gt(),
+                        _ => ord.is_ge(),
+                    }),
+                },
+                _ => arithmetic(*op, &l, &r),
+            }
+        }
+        Expr::IsNull { expr, negated } => Value::Bool(eval(expr, group)?.is_null() != *negated),
+        Expr::InList {
+            expr,
+            list,
+            negated,
+        } => {
+            let v = eval(expr, group)?;
+            if v.is_null() {
+                return Ok(Value::Null);
+            }
+            let mut found = false;
+            for item in list {
+                if compare(&v, &eval(item, group)?) == Some(Ordering::Equal) {
+                    found = true;
+                    break;
+                }
+            }
+            Value::Bool(found != *negated)
+        }
+        Expr::Like {
+            expr,
+            pattern,
+            negated,
+        } => match (eval(expr, group)?, eval(pattern, group)?) {
+            (Value::Null, _) | (_, Value::Null) => Value::Null,
+            (v, p) => {
+                let text: Vec<char> = text_of(&v).to_lowercase().chars().collect();
+                let pattern: Vec<char> = text_of(&p).to_lowercase().chars().collect();
+                Value::Bool(like(&text, &pattern) != *negated)
+            }
+        },
+        Expr::Aggregate { func, arg } => {
+            let values = match arg {
+                None => return Ok(Value::from(group.len())),
+                Some(arg) => group
+                    .iter()
+                    .map(|row| eval(arg, &[row]))
+                    .filter(|v| !matches!(v, Ok(Value::Null)))
+                    .collect::<Result<Vec<_>>>()?,
+            };
+            let numbers = || values.iter().filter_map(to_number);
+            match func {
+                AggregateFn::Count => Value::from(values.len()),
+                AggregateFn::Sum => number_value(numbers().sum()),
+                AggregateFn::Avg => match numbers().count() {
+                    0 => Value::Null,
+                    n => number_value(numbers().sum::<f64>() / n as f64),
+                },
+                AggregateFn::Min => values.into_iter().min_by(sort_order).unwrap_or(Value::Null),
+                AggregateFn::Max => values.into_iter().max_by(sort_order).unwrap_or(Value::Null),
+            }
+        }
+    })
+}
+
+// SQL's three valued logic: null is neither true nor false
+fn truth(value: &Value) -> Option<bool> {
+    match value {
+        Value::Null => None,
+        Value::Bool(b) => Some(*b),
+        Value::Number(n) => Some(n.as_f64() != Some(0.0)),
+        Value::String(s) => Some(!s.is_empty()),
+        _ => Some(true),
+    }
+}
+
+fn to_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn text_of(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        v => v.to_string(),
+    }
+}
+
+// numbers compare numerically (also against numeric text), everything else as text
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Null, _) | (_, Value::Null) => None,
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        (Value::Number(_), _) | (_, Value::Number(_)) => match (to_number(a), to_number(b)) {
+            (Some(a), Some(b)) => a.partial_cmp(&b),
+            _ => Some(text_of(a).cmp(&text_of(b))),
+        },
+        _ => Some(text_of(a).cmp(&text_of(b))),
+    }
+}
+
+// total order for sorting, nulls first
+fn sort_order(a: &Value, b: &Value) -> Ordering {
+    match (a.is_null(), b.is_null()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Less,
+        (false, true) => Ordering::Greater,
+        _ => compare(a, b).unwrap_or(Ordering::Equal),
+    }
+}
+
+fn arithmetic(op: BinaryOp, a: &Value, b: &Value) -> Value {
+    let (Some(x), Some(y)) = (to_number(a), to_number(b)) else {
+        return Value::Null;
+    };
+    // integers stay integers unless they overflow or get divided
+    if let (Some(i), Some(j)) = (a.as_i64(), b.as_i64()) {
+        let ret = match op {
+            BinaryOp::Add => i.checked_add(j),
+            BinaryOp::Sub => i.checked_sub(j),
+            BinaryOp::Mul => i.checked_mul(j),
+            BinaryOp::Mod => i.checked_rem(j),
+            _ => None,
+        };
+        if let Some(v) = ret {
+            return Value::from(v);
+        }
+    }
+    let ret = match op {
+        BinaryOp::Add => x + y,
+        BinaryOp::Sub => x - y,
+        BinaryOp::Mul => x * y,
+        BinaryOp::Div => x / y,
+        _ => x % y,
+    };
+    Number::from_f64(ret).map_or(Value::Null, Value::Number)
+}
+
+// `%` matches any run of characters and `_` a single one
+fn like(text: &[char], pattern: &[char]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some(('%', rest)) => (0..=text.len()).any(|i| like(&text[i..], rest)),
+        Some(('_', rest)) => !text.is_empty() && like(&text[1..], rest),
+        Some((c, rest)) => text.first() == Some(c) && like(&text[1..], rest),
+    }
+}
+
+fn tokenize(sql: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Word(chars[start..i].iter().collect()));
+        } else if c.is_ascii_digit()
+            || (c == '.' && chars.get(i + 1).is_some_and(char::is_ascii_digit))
+        {
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            tokens.push(Token::Num(chars[start..i].iter().collect()));
+        } else if matches!(c, '\'' | '"' | '`' | '[') {
+            let close = if c == '[' { ']' } else { c };
+            let mut text = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err(anyhow!("Unterminated {} at position {}", c, start)),
+                    // a doubled quote stands for the quote itself
+                    Some(&q) if q == close && chars.get(i + 1) == Some(&close) && c != '[' => {
+                        text.push(q);
+                        i += 2;
+                    }
+                    Some(&q) if q == close => {
+                        i += 1;
+                        break;
+                    }
+                    Some(&ch) => {
+                        text.push(ch);
+                        i += 1;
+                    }
+                }
+            }
+            tokens.push(if c == '\'' {
+                Token::Str(text)
+            } else {
+                Token::Ident(text)
+            });
+        } else {
+            let (sym, len) = match (c, chars.get(i + 1)) {
+                ('<', Some('=')) => ("<=", 2),
+                ('>', Some('=')) => (">=", 2),
+                ('<', Some('>')) | ('!', Some('=')) => ("!=", 2),
+                ('=', Some('=')) => ("=", 2),
+                (',', _) => (",", 1),
+                ('(', _) => ("(", 1),
+                (')', _) => (")", 1),
+                ('*', _) => ("*", 1),
+                ('.', _) => (".", 1),
+                ('=', _) => ("=", 1),
+                ('<', _) => ("<", 1),
+                ('>', _) => (">", 1),
+                ('+', _) => ("+", 1),
+                ('-', _) => ("-", 1),
+                ('/', _) => ("/", 1),
+                ('%', _) => ("%", 1),
+                (';', _) => (";", 1),
+                _ => return Err(anyhow!("Unexpected character {:?} at position {}", c, i)),
+            };
+            i += len;
+            tokens.push(Token::Sym(sym));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(w)) if w.eq_ignore_ascii_case(keyword))
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found = self.peek_keyword(keyword);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<()> {
+        if self.keyword(keyword) {
+            Ok(())
+        } else {
+            Err(self.unexpected(keyword))
+        }
+    }
+
+    fn symbol(&mut self, sym: &'static str) -> bool {
+        let found = self.peek() == Some(&Token::Sym(sym));
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect_symbol(&mut self, sym: &'static str) -> Result<()> {
+        if self.symbol(sym) {
+            Ok(())
+        } else {
+            Err(self.unexpected(sym))
+        }
+    }
+
+    fn unexpected(&self, expected: &str) -> anyhow::Error {
+        match self.peek() {
+            Some(token) => anyhow!("Expected {} but found {}", expected, token),
+            None => anyhow!("Expected {} but the query ended", expected),
+        }
+    }
+
+    fn identifier(&mut self) -> Result<String> {
+        match self.peek().cloned() {
+            Some(Token::Word(w)) if !is_keyword(&w) => {
+                self.pos += 1;
+                Ok(w)
+            }
+            Some(Token::Ident(w)) => {
+                self.pos += 1;
+                Ok(w)
+            }
+            _ => Err(self.unexpected("a name")),
+        }
+    }
+
+    // `AS alias` or a bare alias
+    fn alias(&mut self) -> Result<Option<String>> {
+        if self.keyword("AS") {
+            return self.identifier().map(Some);
+        }
+        match self.peek() {
+            Some(Token::Word(w)) if !is_keyword(w) => self.identifier().map(Some),
+            Some(Token::Ident(_)) => self.identifier().map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    fn query(&mut self) -> Result<SqlQuery> {
+        self.expect_keyword("SELECT")?;
+        let distinct = self.keyword("DISTINCT");
+        let mut items = Vec::new();
+        loop {
+            items.push(self.select_item()?);
+            if !self.symbol(",") {
+                break;
+            }
+        }
+        self.expect_keyword("FROM")?;
+        let from = self.table_ref()?;
+
+        let mut joins = Vec::new();
+        loop {
+            let kind = if self.keyword("LEFT") {
+                self.keyword("OUTER");
+                JoinKind::Left
+            } else if self.keyword("CROSS") {
+                self.expect_keyword("JOIN")?;
+                joins.push(Join {
+                    kind: JoinKind::Inner,
+                    table: self.table_ref()?,
+                    on: None,
+                });
+                continue;
+            } else if self.keyword("INNER") || self.peek_keyword("JOIN") {
+                JoinKind::Inner
+            } else {
+                break;
+            };
+            self.expect_keyword("JOIN")?;
+            let table = self.table_ref()?;
+            self.expect_keyword("ON")?;
+            joins.push(Join {
+                kind,
+                table,
+                on: Some(self.expr()?),
+            });
+        }
+
+        let filter = if self.keyword("WHERE") {
+            Some(self.expr()?)
+        } else {
+            None
+        };
+        let mut group_by = Vec::new();
+        if self.keyword("GROUP") {
+            self.expect_keyword("BY")?;
+            loop {
+                group_by.push(self.expr()?);
+                if !self.symbol(",") {
+                    break;
+                }
+            }
+        }
+        let having = if self.keyword("HAVING") {
+            Some(self.expr()?)
+        } else {
+            None
+        };
+        let mut order_by = Vec::new();
+        if self.keyword("ORDER") {
+            self.expect_keyword("BY")?;
+            loop {
+                let expr = self.expr()?;
+                let desc = self.keyword("DESC");
+                if !desc {
+                    self.keyword("ASC");
+                }
+                order_by.push((expr, desc));
+                if !self.symbol(",") {
+                    break;
+                }
+            }
+        }
+        let limit = if self.keyword("LIMIT") {
+            Some(self.count()?)
+        } else {
+            None
+        };
+        let offset = if self.keyword("OFFSET") {
+            self.count()?
+        } else {
+            0
+        };
+        self.symbol(";");
+        if self.peek().is_some() {
+            return Err(self.unexpected("the end of the query"));
+        }
+        Ok(SqlQuery {
+            distinct,
+            items,
+            from,
+            joins,
+            filter,
+            group_by,
+            having,
+            order_by,
+            limit,
+            offset,
+        })
+    }
+
+    fn count(&mut self) -> Result<usize> {
+        match self.peek().cloned() {
+            Some(Token::Num(n)) => {
+                self.pos += 1;
+                n.parse()
+                    .map_err(|_| anyhow!("Expected a row count, got {}", n))
+            }
+            _ => Err(self.unexpected("a row count")),
+        }
+    }
+
+    fn table_ref(&mut self) -> Result<TableRef> {
+        let name = self.identifier()?;
+        let alias = self.alias()?.unwrap_or_else(|| name.clone());
+        Ok(TableRef { name, alias })
+    }
+
+    fn select_item(&mut self) -> Result<SelectItem> {
+        if self.symbol("*") {
+            return Ok(SelectItem::Wildcard(None));
+        }
+        // t.*
+        if let (
+            Some(Token::Word(_) | Token::Ident(_)),
+            Some(Token::Sym(".")),
+            Some(Token::Sym("*")),
+        ) = (
+            self.tokens.get(self.pos),
+            self.tokens.get(self.pos + 1),
+            self.tokens.get(self.pos + 2),
+        ) {
+            let table = self.identifier()?;
+            self.pos += 2;
+            return Ok(SelectItem::Wildcard(Some(table)));
+        }
+        let expr = self.expr()?;
+        let alias = self.alias()?;
+        Ok(SelectItem::Expr { expr, alias })
+    }
+
+    fn expr(&mut self) -> Result<Expr> {
+        let mut left = self.and()?;
+        while self.keyword("OR") {
+            left = binary(BinaryOp::Or, left, self.and()?);
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut left = self.not()?;
+        while self.keyword("AND") {
+            left = binary(BinaryOp::And, left, self.not()?);
+        }
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<Expr> {
+        if self.keyword("NOT") {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr> {
+        let left = self.additive()?;
+        if let Some(Token::Sym(sym)) = self.peek() {
+            let op = match *sym {
+                "=" => Some(BinaryOp::Eq),
+                "!=" => Some(BinaryOp::Ne),
+                "<" => Some(BinaryOp::Lt),
+                "<=" => Some(BinaryOp::Le),
+                ">" => Some(BinaryOp::Gt),
+                ">=" => Some(BinaryOp::Ge),
+                _ => None,
+            };
+            if let Some(op) = op {
+                self.pos += 1;
+                return Ok(binary(op, left, self.additive()?));
+            }
+        }
+        if self.keyword("IS") {
+            let negated = self.keyword("NOT");
+            self.expect_keyword("NULL")?;
+            return Ok(Expr::IsNull {
+                expr: Box::new(left),
+                negated,
+            });
+        }
+        let negated = self.keyword("NOT");
+        if self.keyword("IN") {
+            self.expect_symbol("(")?;
+            let mut list = Vec::new();
+            loop {
+                list.push(self.expr()?);
+                if !self.symbol(",") {
+                    break;
+                }
+            }
+            self.expect_symbol(")")?;
+            return Ok(Expr::InList {
+                expr: Box::new(left),
+                list,
+                negated,
+            });
+        }
+        if self.keyword("LIKE") {
+            return Ok(Expr::Like {
+                expr: Box::new(left),
+                pattern: Box::new(self.additive()?),
+                negated,
+            });
+        }
+        if self.keyword("BETWEEN") {
+            let low = self.additive()?;
+            self.expect_keyword("AND")?;
+            let high = self.additive()?;
+            let between = binary(
+                BinaryOp::And,
+                binary(BinaryOp::Ge, left.clone(), low),
+                binary(BinaryOp::Le, left, high),
+            );
+            return Ok(if negated {
+                Expr::Not(Box::new(between))
+            } else {
+                between
+            });
+        }
+        if negated {
+            return Err(self.unexpected("IN, LIKE or BETWEEN after NOT"));
+        }
+        Ok(left)
+    }
+
+    fn additive(&mut self) -> Result<Expr> {
+        let mut left = self.multiplicative()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Sym("+")) => BinaryOp::Add,
+                Some(Token::Sym("-")) => BinaryOp::Sub,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            left = binary(op, left, self.multiplicative()?);
+        }
+    }
+
+    fn multiplicative(&mut self) -> Result<Expr> {
+        let mut left = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Sym("*")) => BinaryOp::Mul,
+                Some(Token::Sym("/")) => BinaryOp::Div,
+                Some(Token::Sym("%")) => BinaryOp::Mod,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            left = binary(op, left, self.unary()?);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        if self.peek() == Some(&Token::Sym("-")) {
+            self.pos += 1;
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        let token = self
+            .peek()
+            .cloned()
+            .ok_or_else(|| self.unexpected("an expression"))?;
+        match token {
+            Token::Num(n) => {
+                self.pos += 1;
+                match infer_value(&n) {
+                    v @ Value::Number(_) => Ok(Expr::Literal(v)),
+                    _ => Err(anyhow!("Invalid number: {}", n)),
+                }
+            }
+            Token::Str(s) => {
+                self.pos += 1;
+                Ok(Expr::Literal(Value::String(s)))
+            }
+            Token::Sym("(") => {
+                self.pos += 1;
+                let expr = self.expr()?;
+                self.expect_symbol(")")?;
+                Ok(expr)
+            }
+            Token::Word(w) if w.eq_ignore_ascii_case("NULL") => {
+                self.pos += 1;
+                Ok(Expr::Literal(Value::Null))
+            }
+            Token::Word(w) if w.eq_ignore_ascii_case("TRUE") || w.eq_ignore_ascii_case("FALSE") => {
+                self.pos += 1;
+                Ok(Expr::Literal(Value::Bool(w.eq_ignore_ascii_case("TRUE"))))
+            }
+            Token::Word(w) if self.tokens.get(self.pos + 1) == Some(&Token::Sym("(")) => {
+                self.pos += 2;
+                let func = match w.to_lowercase().as_str() {
+                    "count" => AggregateFn::Count,
+                    "sum" => AggregateFn::Sum,
+                    "avg" | "mean" => AggregateFn::Avg,
+                    "min" => AggregateFn::Min,
+                    "max" => AggregateFn::Max,
+                    _ => return Err(anyhow!("Unknown function: {}", w)),
+                };
+                let arg = if func == AggregateFn::Count && self.symbol("*") {
+                    None
+                } else {
+                    Some(Box::new(self.expr()?))
+                };
+                self.expect_symbol(")")?;
+                Ok(Expr::Aggregate { func, arg })
+            }
+            _ => {
+                let name = self.identifier()?;
+                if self.symbol(".") {
+                    Ok(Expr::Column {
+                        table: Some(name),
+                        name: self.identifier()?,
+                    })
+                } else {
+                    Ok(Expr::Column { table: None, name })
+                }
+            }
+        }
+    }
+}
+
+fn binary(op: BinaryOp, left: Expr, right: Expr) -> Expr {
+    Expr::Binary {
+        op,
+        left: Box::new(left),
+        right: Box::new(right),
+    }
+}
+
+fn is_keyword(word: &str) -> bool {
+    KEYWORDS.iter().any(|k| k.eq_ignore_ascii_case(word))
+}
+
+impl FromStr for SqlQuery {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Parser {
+            tokens: tokenize(s)?,
+            pos: 0,
+        }
+        .query()
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(w) | Token::Num(w) => write!(f, "{}", w),
+            Token::Ident(w) => write!(f, "\"{}\"", w),
+            Token::Str(s) => write!(f, "'{}'", s),
+            Token::Sym(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl From<BinaryOp> for &'static str {
+    fn from(value: BinaryOp) -> Self {
+        match value {
+            BinaryOp::Eq => "=",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::And => "AND",
+            BinaryOp::Or => "OR",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Mod => "%",
+        }
+    }
+}
+
+// also used to name output columns, e.g. `sum(Salary)` like `csv stats` does
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Literal(Value::String(s)) => write!(f, "'{}'", s),
+            Expr::Literal(v) => write!(f, "{}", v),
+            Expr::Column {
+                table: Some(t),
+                name,
+            } => write!(f, "{}.{}", t, name),
+            Expr::Column { table: None, name } => write!(f, "{}", name),
+            Expr::Bound(i) => write!(f, "#{}", i),
+            Expr::Not(e) => write!(f, "NOT {}", e),
+            Expr::Neg(e) => write!(f, "-{}", e),
+            Expr::Binary { op, left, right } => {
+                let op: &str = (*op).into();
+                write!(f, "{} {} {}", left, op, right)
+            }
+            Expr::IsNull { expr, negated } => {
+                write!(f, "{} IS {}NULL", expr, if *negated { "NOT " } else { "" })
+            }
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                let list: Vec<String> = list.iter().map(|e| e.to_string()).collect();
+                let not = if *negated { "NOT " } else { "" };
+                write!(f, "{} {}IN ({})", expr, not, list.join(", "))
+            }
+            Expr::Like {
+                expr,
+                pattern,
+                negated,
+            } => {
+                let not = if *negated { "NOT " } else { "" };
+                write!(f, "{} {}LIKE {}", expr, not, pattern)
+            }
+            Expr::Aggregate { func, arg } => {
+                let func: &str = (*func).into();
+                match arg {
+                    Some(arg) => write!(f, "{}({})", func, arg),
+                    None => write!(f, "{}(*)", func),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn tables() -> Result<HashMap<String, CsvTable>> {
+        let players = "\
+id,name,team,age
+1,Buffon,Juventus,41
+2,Perin,Juventus,26
+3,Szczesny,Juventus,29
+4,Donnarumma,Milan,20
+5,Handanovic,Inter,35
+";
+        let contracts = "\
+player_id,salary
+1,2.5
+3,7
+4,6
+4,1
+";
+        let loans = "player_id,club\n";
+        let read = CsvReadOptions::default();
+        Ok(HashMap::from([
+            (
+                "players".to_string(),
+                CsvTable::load(players.as_bytes(), &read)?,
+            ),
+            (
+                "contracts".to_string(),
+                CsvTable::load(contracts.as_bytes(), &read)?,
+            ),
+            (
+                "loans".to_string(),
+                CsvTable::load(loans.as_bytes(), &read)?,
+            ),
+        ]))
+    }
+
+    fn query(sql: &str) -> Result<Value> {
+        let mut buf = Vec::new();
+        sql.parse::<SqlQuery>()?
+            .execute(&tables()?, &mut buf, OutputFormat::Json)?;
+        Ok(serde_json::from_slice(&buf)?)
+    }
+
+    #[test]
+    fn test_select_where_order_limit() -> Result<()> {
+        assert_eq!(
+            query("SELECT name, age FROM players WHERE age > 25 ORDER BY age DESC LIMIT 2")?,
+            json!([{"name": "Buffon", "age": 41}, {"name": "Handanovic", "age": 35}])
+        );
+        assert_eq!(
+            query("select name as n from players where team in ('Milan', 'Inter') and name like 'd%' order by 1")?,
+            json!([{"n": "Donnarumma"}])
+        );
+        assert_eq!(
+            query("SELECT DISTINCT team FROM players WHERE age BETWEEN 20 AND 30 ORDER BY team")?,
+            json!([{"team": "Juventus"}, {"team": "Milan"}])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_group_by_and_aggregates() -> Result<()> {
+        assert_eq!(
+            query("SELECT team, count(*), avg(age) AS mean_age FROM players GROUP BY team HAVING count(*) > 1 OR team = 'Milan' ORDER BY mean_age")?,
+            json!([
+                {"team": "Milan", "count(*)": 1, "mean_age": 20},
+                {"team": "Juventus", "count(*)": 3, "mean_age": 32},
+            ])
+        );
+        assert_eq!(
+            query(
+                "SELECT COUNT(*) AS n, MAX(age) - MIN(age) AS spread FROM players WHERE age > 100"
+            )?,
+            json!([{"n": 0, "spread": null}])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_joins() -> Result<()> {
+        assert_eq!(
+            query("SELECT p.name, sum(c.salary) AS total FROM players p JOIN contracts c ON p.id = c.player_id GROUP BY p.name ORDER BY total DESC")?,
+            json!([
+                {"name": "Szczesny", "total": 7},
+                {"name": "Donnarumma", "total": 7},
+                {"name": "Buffon", "total": 2.5},
+            ])
+        );
+        assert_eq!(
+            query("SELECT name, salary FROM players LEFT JOIN contracts ON player_id = id WHERE salary IS NULL ORDER BY name")?,
+            json!([{"name": "Handanovic", "salary": null}, {"name": "Perin", "salary": null}])
+        );
+        // a right table with only a header still pads with its columns
+        assert_eq!(
+            query("SELECT * FROM players p LEFT JOIN loans l ON p.id = l.player_id WHERE l.club IS NULL AND p.id = 1")?,
+            json!([{"id": 1, "name": "Buffon", "team": "Juventus", "age": 41, "player_id": null, "club": null}])
+        );
+        assert_eq!(
+            query("SELECT name FROM players p LEFT JOIN loans l ON l.club <> p.team ORDER BY name LIMIT 1")?,
+            json!([{"name": "Buffon"}])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_errors() -> Result<()> {
+        assert!(query("SELECT nope FROM players").is_err());
+        assert!(query("SELECT name FROM missing").is_err());
+        assert!(query("SELECT name FROM players WHERE count(*) > 1").is_err());
+        assert!("SELECT name FROM players WHERE"
+            .parse::<SqlQuery>()
+            .is_err());
+        assert!("SELECT 'open FROM players".parse::<SqlQuery>().is_err());
+        let q: SqlQuery = "SELECT * FROM a JOIN b ON a.x = b.x LEFT JOIN a ON 1 = 1".parse()?;
+        assert_eq!(q.tables(), ["a", "b"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,273 @@
+use std::io::{self, Read};
+
+use super::{decode_reader, InputEncoding};
+use anyhow::{anyhow, Result};
+use csv::{ByteRecord, Reader, ReaderBuilder, StringRecord};
+use encoding_rs::Encoding;
+
+// multi-byte delimiters are mapped onto the ASCII unit separator before parsing
+const UNIT_SEPARATOR: u8 = 0x1f;
+
+#[derive(Debug, Clone)]
+pub struct CsvReadOptions {
+    pub delimiter: String,
+    pub has_headers: bool,
+    pub columns: Option<Vec<String>>,
+    // allow records with a different number of fields than the header
+    pub flexible: bool,
+    // input is transcoded to UTF-8 before parsing unless it's UTF-8 already
+    pub encoding: InputEncoding,
+}
+
+impl Default for CsvReadOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: ",".into(),
+            has_headers: true,
+            columns: None,
+            flexible: false,
+            encoding: InputEncoding::default(),
+        }
+    }
+}
+
+/// Records of a csv input together with the column names used to key them,
+/// either read from the header row, given explicitly, or synthesized as
+/// `col_0..col_n` for headerless files.
+pub struct CsvRecords<'a> {
+    reader: Reader<Box<dyn Read + 'a>>,
+    headers: StringRecord,
+    pending: Option<StringRecord>,
+    multi_byte: Option<String>,
+    encoding: &'static Encoding,
+}
+
+impl<'a> CsvRecords<'a> {
+    pub fn new(reader: impl Read + 'a, opts: &CsvReadOptions) -> Result<Self> {
+        let (reader, encoding) = decode_reader(reader, opts.encoding)?;
+        let delimiter = opts.delimiter.as_bytes();
+        let (reader, delimiter, multi_byte): (Box<dyn Read + 'a>, u8, _) = match delimiter {
+            [] => return Err(anyhow!("Delimiter can't be empty")),
+            [b] => (reader, *b, None),
+            _ => (
+                Box::new(DelimiterReader::new(reader, delimiter.to_vec())),
+                UNIT_SEPARATOR,
+                Some(opts.delimiter.clone()),
+            ),
+        };
+        let mut reader = ReaderBuilder::new()
+            .delimiter(delimiter)
+            .has_headers(false)
+            .flexible(opts.flexible)
+            .from_reader(reader);
+
+        let mut first = StringRecord::new();
+        let has_first = reader.read_record(&mut first)?;
+        let (mut headers, pending) = if opts.has_headers {
+            (first, None)
+        } else if has_first {
+            let headers = (0..first.len()).map(|i| format!("col_{}", i)).collect();
+            (headers, Some(first))
+        } else {
+            (StringRecord::new(), None)
+        };
+        if let Some(delimiter) = &multi_byte {
+            restore_delimiter(&mut headers, delimiter);
+        }
+        if let Some(columns) = &opts.columns {
+            if has_first && columns.len() != headers.len() {
+                return Err(anyhow!(
+                    "Got {} column names but the input has {} columns",
+                    columns.len(),
+                    headers.len()
+                ));
+            }
+            headers = columns.iter().collect();
+        }
+        Ok(Self {
+            reader,
+            headers,
+            pending,
+            multi_byte,
+            encoding,
+        })
+    }
+
+    pub fn headers(&self) -> &StringRecord {
+        &self.headers
+    }
+
+    // the encoding the input was read with, detected or given
+    pub fn encoding(&self) -> &'static Encoding {
+        self.encoding
+    }
+
+    pub fn read_record(&mut self, record: &mut StringRecord) -> Result<bool> {
+        if let Some(pending) = self.pending.take() {
+            *record = pending;
+        } else if !self.reader.read_record(record)? {
+            return Ok(false);
+        }
+        if let Some(delimiter) = &self.multi_byte {
+            restore_delimiter(record, delimiter);
+        }
+        Ok(true)
+    }
+
+    // like read_record, but leaves invalid UTF-8 for the caller to report
+    pub fn read_byte_record(&mut self, record: &mut ByteRecord) -> Result<bool> {
+        if let Some(pending) = self.pending.take() {
+            *record = pending.into_byte_record();
+        } else if !self.reader.read_byte_record(record)? {
+            return Ok(false);
+        }
+        if let Some(delimiter) = &self.multi_byte {
+            if record.as_slice().contains(&UNIT_SEPARATOR) {
+                let position = record.position().cloned();
+                *record = record
+                    .iter()
+                    .map(|field| {
+                        field
+                            .split(|b| *b == UNIT_SEPARATOR)
+                            .collect::<Vec<_>>()
+                            .join(delimiter.as_bytes())
+                    })
+                    .collect();
+                record.set_position(position);
+            }
+        }
+        Ok(true)
+    }
+}
+
+// quoted fields may legitimately contain a multi-byte delimiter, map it back
+fn restore_delimiter(record: &mut StringRecord, delimiter: &str) {
+    if record.as_slice().as_bytes().contains(&UNIT_SEPARATOR) {
+        let position = record.position().cloned();
+        *record = record
+            .iter()
+            .map(|field| field.replace(UNIT_SEPARATOR as char, delimiter))
+            .collect();
+        record.set_position(position);
+    }
+}
+
+// replaces every occurrence of a multi-byte delimiter with the unit separator
+struct DelimiterReader<R> {
+    inner: R,
+    delimiter: Vec<u8>,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    pos: usize,
+    eof: bool,
+}
+
+impl<R: Read> DelimiterReader<R> {
+    fn new(inner: R, delimiter: Vec<u8>) -> Self {
+        Self {
+            inner,
+            delimiter,
+            input: Vec::new(),
+            output: Vec::new(),
+            pos: 0,
+            eof: false,
+        }
+    }
+
+    fn fill(&mut self) -> io::Result<()> {
+        let mut chunk = [0u8; 8 * 1024];
+        let n = self.inner.read(&mut chunk)?;
+        self.eof = n == 0;
+        self.input.extend_from_slice(&chunk[..n]);
+
+        self.output.clear();
+        self.pos = 0;
+        let mut i = 0;
+        while i < self.input.len() {
+            let rest = &self.input[i..];
+            if rest.starts_with(&self.delimiter) {
+                self.output.push(UNIT_SEPARATOR);
+                i += self.delimiter.len();
+            } else if !self.eof && self.delimiter.starts_with(rest) {
+                // possibly a delimiter split across two reads
+                break;
+            } else if rest[0] == UNIT_SEPARATOR {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "input contains a unit separator (0x1f), which is reserved for multi-byte delimiters",
+                ));
+            } else {
+                self.output.push(rest[0]);
+                i += 1;
+            }
+        }
+        self.input.drain(..i);
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DelimiterReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.output.len() {
+            if self.eof {
+                return Ok(0);
+            }
+            self.fill()?;
+        }
+        let n = buf.len().min(self.output.len() - self.pos);
+        buf[..n].copy_from_slice(&self.output[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(input: &str, opts: &CsvReadOptions) -> Result<(Vec<String>, Vec<Vec<String>>)> {
+        let mut records = CsvRecords::new(input.as_bytes(), opts)?;
+        let headers = records.headers().iter().map(String::from).collect();
+        let mut rows = Vec::new();
+        let mut record = StringRecord::new();
+        while records.read_record(&mut record)? {
+            rows.push(record.iter().map(String::from).collect());
+        }
+        Ok((headers, rows))
+    }
+
+    #[test]
+    fn test_multi_byte_delimiter() -> Result<()> {
+        let opts = CsvReadOptions {
+            delimiter: "||".into(),
+            ..Default::default()
+        };
+        let (headers, rows) = read_all("a||b\n\"x||y\"||z\n1|2||3\n", &opts)?;
+        assert_eq!(headers, ["a", "b"]);
+        assert_eq!(rows, [["x||y", "z"], ["1|2", "3"]]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_multi_byte_delimiter_across_reads() -> Result<()> {
+        let line = format!("{}§{}\n", "a".repeat(8 * 1024 - 1), "b");
+        let opts = CsvReadOptions {
+            delimiter: "§".into(),
+            has_headers: false,
+            ..Default::default()
+        };
+        let (headers, rows) = read_all(&line, &opts)?;
+        assert_eq!(headers, ["col_0", "col_1"]);
+        assert_eq!(rows[0][1], "b");
+        Ok(())
+    }
+
+    #[test]
+    fn test_columns_mismatch() {
+        let opts = CsvReadOptions {
+            columns: Some(vec!["a".into()]),
+            ..Default::default()
+        };
+        assert!(read_all("x,y\n1,2\n", &opts).is_err());
+    }
+}
@@ -0,0 +1,383 @@
+use std::{collections::HashMap, fmt, fs, str::FromStr};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use csv::StringRecord;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Number, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnType {
+    String,
+    Integer,
+    Float,
+    Boolean,
+    Date,
+    Datetime,
+}
+
+/// Column types pinned by a schema file, e.g. `{"Kit Number": "integer"}`,
+/// or `{"Kit Number": {"type": "integer", "required": true}}` to also reject
+/// empty cells. Both JSON and YAML files are accepted.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct CsvSchema {
+    pub columns: HashMap<String, ColumnSpec>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(from = "ColumnSpecDef")]
+pub struct ColumnSpec {
+    pub ty: ColumnType,
+    pub required: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColumnSpecDef {
+    Type(ColumnType),
+    Spec {
+        #[serde(rename = "type")]
+        ty: ColumnType,
+        #[serde(default)]
+        required: bool,
+    },
+}
+
+impl From<ColumnSpecDef> for ColumnSpec {
+    fn from(value: ColumnSpecDef) -> Self {
+        match value {
+            ColumnSpecDef::Type(ty) => ty.into(),
+            ColumnSpecDef::Spec { ty, required } => Self { ty, required },
+        }
+    }
+}
+
+impl From<ColumnType> for ColumnSpec {
+    fn from(ty: ColumnType) -> Self {
+        Self {
+            ty,
+            required: false,
+        }
+    }
+}
+
+impl CsvSchema {
+    pub fn load(path: &str) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        serde_yaml::from_str(&content).map_err(|e| anyhow!("Invalid schema {}: {}", path, e))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoercionError {
+    pub line: u64,
+    pub column: usize,
+    pub name: String,
+    pub value: String,
+    pub expected: ColumnType,
+}
+
+impl fmt::Display for CoercionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {} ({}): ",
+            self.line, self.column, self.name
+        )?;
+        if self.value.is_empty() {
+            write!(f, "a {} value is required", self.expected)
+        } else {
+            write!(f, "{:?} is not a valid {}", self.value, self.expected)
+        }
+    }
+}
+
+/// Turns raw csv records into JSON objects, typing each cell either by the
+/// schema, by inference, or leaving it as a string.
+pub struct RecordTyper {
+    headers: Vec<String>,
+    types: Vec<Option<ColumnSpec>>,
+    infer: bool,
+}
+
+impl RecordTyper {
+    pub fn new(headers: &StringRecord, schema: Option<&CsvSchema>, infer: bool) -> Result<Self> {
+        let headers: Vec<String> = headers.iter().map(String::from).collect();
+        let mut types = vec![None; headers.len()];
+        if let Some(schema) = schema {
+            for (name, ty) in &schema.columns {
+                let idx = headers
+                    .iter()
+                    .position(|h| h == name)
+                    .ok_or_else(|| anyhow!("Schema column {:?} not found in input", name))?;
+                types[idx] = Some(*ty);
+            }
+        }
+        Ok(Self {
+            headers,
+            types,
+            infer,
+        })
+    }
+
+    pub fn headers(&self) -> &[String] {
+        &self.headers
+    }
+
+    pub fn to_value(
+        &self,
+        record: &StringRecord,
+        errors: &mut Vec<CoercionError>,
+    ) -> Map<String, Value> {
+        let mut map = Map::with_capacity(self.headers.len());
+        for (i, (name, field)) in self.headers.iter().zip(record.iter()).enumerate() {
+            let value = match self.types[i] {
+                Some(spec) => coerce_value(field, spec.ty)
+                    .filter(|v| !(spec.required && (v.is_null() || field.is_empty())))
+                    .unwrap_or_else(|| {
+                        errors.push(CoercionError {
+                            line: record.position().map_or(0, |p| p.line()),
+                            column: i + 1,
+                            name: name.clone(),
+                            value: field.to_string(),
+                            expected: spec.ty,
+                        });
+                        Value::String(field.to_string())
+                    }),
+                None if self.infer => infer_value(field),
+                None => Value::String(field.to_string()),
+            };
+            map.insert(name.clone(), value);
+        }
+        map
+    }
+}
+
+// the narrowest type able to hold values of both types
+pub fn widen_type(a: ColumnType, b: ColumnType) -> ColumnType {
+    use ColumnType::*;
+    match (a, b) {
+        (a, b) if a == b => a,
+        (Integer, Float) | (Float, Integer) => Float,
+        (Date, Datetime) | (Datetime, Date) => Datetime,
+        _ => String,
+    }
+}
+
+pub fn infer_type(field: &str) -> Option<ColumnType> {
+    if field.is_empty() {
+        None
+    } else if matches!(
+        field,
+        "true" | "false" | "TRUE" | "FALSE" | "True" | "False"
+    ) {
+        Some(ColumnType::Boolean)
+    } else if parse_integer(field).is_some() {
+        Some(ColumnType::Integer)
+    } else if parse_float(field).is_some() {
+        Some(ColumnType::Float)
+    } else if parse_date(field).is_some() {
+        Some(ColumnType::Date)
+    } else if parse_datetime(field).is_some() {
+        Some(ColumnType::Datetime)
+    } else {
+        Some(ColumnType::String)
+    }
+}
+
+pub fn infer_value(field: &str) -> Value {
+    match infer_type(field) {
+        None => Value::Null,
+        Some(ty) => coerce_value(field, ty).unwrap_or_else(|| Value::String(field.to_string())),
+    }
+}
+
+// empty cells of a typed column become null, anything unparsable is `None`
+pub fn coerce_value(field: &str, ty: ColumnType) -> Option<Value> {
+    if field.is_empty() && ty != ColumnType::String {
+        return Some(Value::Null);
+    }
+    let value = match ty {
+        ColumnType::String => Value::String(field.to_string()),
+        ColumnType::Integer => Value::Number(parse_integer(field)?.into()),
+        ColumnType::Float => Value::Number(Number::from_f64(parse_float(field)?)?),
+        ColumnType::Boolean => Value::Bool(parse_boolean(field)?),
+        ColumnType::Date => Value::String(parse_date(field)?.to_string()),
+        ColumnType::Datetime => {
+            parse_datetime(field)?;
+            Value::String(field.to_string())
+        }
+    };
+    Some(value)
+}
+
+fn parse_integer(field: &str) -> Option<i64> {
+    let digits = field.strip_prefix(['-', '+']).unwrap_or(field);
+    // keep zero padded codes like `007` as they are
+    if digits.is_empty() || (digits.len() > 1 && digits.starts_with('0')) {
+        return None;
+    }
+    field.parse().ok()
+}
+
+fn parse_float(field: &str) -> Option<f64> {
+    let digits = field.strip_prefix(['-', '+']).unwrap_or(field);
+    // reject `inf`, `NaN` and friends, which rust happily parses
+    if !digits.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+        return None;
+    }
+    let int_part = digits.split(['.', 'e', 'E']).next().unwrap_or_default();
+    if int_part.len() > 1 && int_part.starts_with('0') {
+        return None;
+    }
+    field.parse::<f64>().ok().filter(|f| f.is_finite())
+}
+
+fn parse_boolean(field: &str) -> Option<bool> {
+    match field.to_lowercase().as_str() {
+        "true" | "t" | "yes" | "y" | "1" => Some(true),
+        "false" | "f" | "no" | "n" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+pub(crate) fn parse_date(field: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(field, "%Y-%m-%d").ok()
+}
+
+pub(crate) fn parse_datetime(field: &str) -> Option<NaiveDateTime> {
+    if let Ok(dt) = DateTime::parse_from_rfc3339(field) {
+        return Some(dt.naive_utc());
+    }
+    ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"]
+        .iter()
+        .find_map(|fmt| NaiveDateTime::parse_from_str(field, fmt).ok())
+}
+
+impl FromStr for ColumnType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "string" => Ok(ColumnType::String),
+            "integer" => Ok(ColumnType::Integer),
+            "float" => Ok(ColumnType::Float),
+            "boolean" => Ok(ColumnType::Boolean),
+            "date" => Ok(ColumnType::Date),
+            "datetime" => Ok(ColumnType::Datetime),
+            _ => Err(anyhow!("Invalid column type")),
+        }
+    }
+}
+
+impl From<ColumnType> for &'static str {
+    fn from(value: ColumnType) -> Self {
+        match value {
+            ColumnType::String => "string",
+            ColumnType::Integer => "integer",
+            ColumnType::Float => "float",
+            ColumnType::Boolean => "boolean",
+            ColumnType::Date => "date",
+            ColumnType::Datetime => "datetime",
+        }
+    }
+}
+
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_infer_value() {
+        assert_eq!(infer_value(""), Value::Null);
+        assert_eq!(infer_value("77"), json!(77));
+        assert_eq!(infer_value("-3"), json!(-3));
+        assert_eq!(infer_value("007"), json!("007"));
+        assert_eq!(infer_value("1.5"), json!(1.5));
+        assert_eq!(infer_value("1e3"), json!(1000.0));
+        assert_eq!(infer_value("NaN"), json!("NaN"));
+        assert_eq!(infer_value("true"), json!(true));
+        assert_eq!(infer_value("yes"), json!("yes"));
+        assert_eq!(infer_type("1990-04-18"), Some(ColumnType::Date));
+        assert_eq!(
+            infer_type("1990-04-18T10:00:00Z"),
+            Some(ColumnType::Datetime)
+        );
+        assert_eq!(infer_type("Apr 18, 1990 (29)"), Some(ColumnType::String));
+        assert_eq!(
+            widen_type(ColumnType::Integer, ColumnType::Float),
+            ColumnType::Float
+        );
+        assert_eq!(
+            widen_type(ColumnType::Boolean, ColumnType::Integer),
+            ColumnType::String
+        );
+    }
+
+    #[test]
+    fn test_coerce_value() {
+        assert_eq!(coerce_value("yes", ColumnType::Boolean), Some(json!(true)));
+        assert_eq!(coerce_value("007", ColumnType::Integer), None);
+        assert_eq!(coerce_value("", ColumnType::Integer), Some(Value::Null));
+        assert_eq!(coerce_value("", ColumnType::String), Some(json!("")));
+        assert_eq!(coerce_value("77", ColumnType::String), Some(json!("77")));
+        assert_eq!(coerce_value("1990-02-30", ColumnType::Date), None);
+    }
+
+    #[test]
+    fn test_record_typer_reports_errors() -> Result<()> {
+        let headers = StringRecord::from(vec!["name", "age", "score"]);
+        let schema = CsvSchema {
+            columns: HashMap::from([("age".to_string(), ColumnType::Integer.into())]),
+        };
+        let typer = RecordTyper::new(&headers, Some(&schema), true)?;
+        let mut errors = Vec::new();
+        let value = typer.to_value(&StringRecord::from(vec!["Perin", "26", "1.5"]), &mut errors);
+        assert_eq!(
+            Value::Object(value),
+            json!({"name": "Perin", "age": 26, "score": 1.5})
+        );
+        assert!(errors.is_empty());
+
+        typer.to_value(&StringRecord::from(vec!["Perin", "n/a", ""]), &mut errors);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            (errors[0].column, errors[0].expected),
+            (2, ColumnType::Integer)
+        );
+
+        let schema = CsvSchema {
+            columns: HashMap::from([("missing".to_string(), ColumnType::Integer.into())]),
+        };
+        assert!(RecordTyper::new(&headers, Some(&schema), false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_schema_column_specs() -> Result<()> {
+        let schema: CsvSchema =
+            serde_yaml::from_str("{age: integer, name: {type: string, required: true}}")?;
+        assert_eq!(schema.columns["age"], ColumnType::Integer.into());
+        assert!(schema.columns["name"].required);
+
+        let headers = StringRecord::from(vec!["name", "age"]);
+        let typer = RecordTyper::new(&headers, Some(&schema), false)?;
+        let mut errors = Vec::new();
+        typer.to_value(&StringRecord::from(vec!["", ""]), &mut errors);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "line 0, column 1 (name): a string value is required"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,365 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashSet},
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+};
+
+use super::{csv_filter::column_index, CsvReadOptions, CsvRecords};
+use anyhow::Result;
+use csv::{ReaderBuilder, StringRecord, Writer, WriterBuilder};
+
+#[derive(Debug, Clone)]
+pub struct SortOptions {
+    pub by: Vec<String>,
+    pub descending: bool,
+    pub numeric: bool,
+    // records held in memory before a sorted run is spilled to disk
+    pub buffer_rows: usize,
+}
+
+impl Default for SortOptions {
+    fn default() -> Self {
+        Self {
+            by: Vec::new(),
+            descending: false,
+            numeric: false,
+            buffer_rows: 100_000,
+        }
+    }
+}
+
+// compares records by the key columns, numbers sort before text in numeric
+// mode, also when descending
+struct KeyComparator {
+    columns: Vec<usize>,
+    descending: bool,
+    numeric: bool,
+}
+
+impl KeyComparator {
+    fn compare(&self, a: &StringRecord, b: &StringRecord) -> Ordering {
+        let direction = |ord: Ordering| {
+            if self.descending {
+                ord.reverse()
+            } else {
+                ord
+            }
+        };
+        self.columns
+            .iter()
+            .map(|&i| {
+                let (a, b) = (a.get(i).unwrap_or_default(), b.get(i).unwrap_or_default());
+                if !self.numeric {
+                    return direction(a.cmp(b));
+                }
+                match (a.trim().parse::<f64>().ok(), b.trim().parse::<f64>().ok()) {
+                    (Some(x), Some(y)) => direction(x.total_cmp(&y)),
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (None, None) => direction(a.cmp(b)),
+                }
+            })
+            .find(|ord| ord.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+/// Sorts csv records with an external merge sort: sorted runs of at most
+/// `buffer_rows` records are spilled to temporary files and merged, so inputs
+/// larger than memory work. The sort is stable. Returns the number of records.
+pub fn sort_csv(
+    reader: impl Read,
+    writer: impl Write,
+    read: &CsvReadOptions,
+    opts: &SortOptions,
+) -> Result<usize> {
+    let mut reader = CsvRecords::new(reader, read)?;
+    let comparator = KeyComparator {
+        columns: opts
+            .by
+            .iter()
+            .map(|name| column_index(reader.headers(), name))
+            .collect::<Result<_>>()?,
+        descending: opts.descending,
+        numeric: opts.numeric,
+    };
+    let mut writer = csv_writer(writer, read);
+    if read.has_headers {
+        writer.write_record(reader.headers())?;
+    }
+
+    let buffer_rows = opts.buffer_rows.max(1);
+    let mut runs = Vec::new();
+    let mut count = 0;
+    loop {
+        let mut chunk = Vec::with_capacity(buffer_rows.min(1024));
+        let mut record = StringRecord::new();
+        while chunk.len() < buffer_rows && reader.read_record(&mut record)? {
+            chunk.push(record.clone());
+        }
+        count += chunk.len();
+        let done = chunk.len() < buffer_rows;
+        chunk.sort_by(|a, b| comparator.compare(a, b));
+
+        if done && runs.is_empty() {
+            // everything fit in memory, no need to touch the disk
+            for record in &chunk {
+                writer.write_record(record)?;
+            }
+            break;
+        }
+        if !chunk.is_empty() {
+            runs.push(spill(&chunk)?);
+        }
+        if done {
+            merge_runs(runs, &comparator, &mut writer)?;
+            break;
+        }
+    }
+    writer.flush()?;
+    Ok(count)
+}
+
+fn spill(chunk: &[StringRecord]) -> Result<File> {
+    let mut file = tempfile::tempfile()?;
+    {
+        let mut writer = WriterBuilder::new().from_writer(BufWriter::new(&mut file));
+        for record in chunk {
+            writer.write_record(record)?;
+        }
+        writer.flush()?;
+    }
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
+
+// the heap is a max-heap, so the ordering is reversed; ties go to the earlier run
+struct HeapItem<'a> {
+    record: StringRecord,
+    run: usize,
+    comparator: &'a KeyComparator,
+}
+
+impl Ord for HeapItem<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.comparator
+            .compare(&self.record, &other.record)
+            .then(self.run.cmp(&other.run))
+            .reverse()
+    }
+}
+
+impl PartialOrd for HeapItem<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for HeapItem<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for HeapItem<'_> {}
+
+fn merge_runs(
+    runs: Vec<File>,
+    comparator: &KeyComparator,
+    writer: &mut Writer<impl Write>,
+) -> Result<()> {
+    let mut readers: Vec<_> = runs
+        .into_iter()
+        .map(|file| {
+            ReaderBuilder::new()
+                .has_headers(false)
+                .from_reader(BufReader::new(file))
+        })
+        .collect();
+    let mut heap = BinaryHeap::with_capacity(readers.len());
+    for (run, reader) in readers.iter_mut().enumerate() {
+        let mut record = StringRecord::new();
+        if reader.read_record(&mut record)? {
+            heap.push(HeapItem {
+                record,
+                run,
+                comparator,
+            });
+        }
+    }
+    while let Some(mut item) = heap.pop() {
+        writer.write_record(&item.record)?;
+        if readers[item.run].read_record(&mut item.record)? {
+            heap.push(item);
+        }
+    }
+    Ok(())
+}
+
+/// Drops records whose key columns (or whole row when no key is given) were
+/// already seen, keeping the first occurrence. Returns the number of records kept.
+pub fn dedup_csv(
+    reader: impl Read,
+    writer: impl Write,
+    read: &CsvReadOptions,
+    keys: &[String],
+) -> Result<usize> {
+    let mut reader = CsvRecords::new(reader, read)?;
+    let columns = keys
+        .iter()
+        .map(|name| column_index(reader.headers(), name))
+        .collect::<Result<Vec<_>>>()?;
+    let mut writer = csv_writer(writer, read);
+    if read.has_headers {
+        writer.write_record(reader.headers())?;
+    }
+
+    let mut seen = HashSet::new();
+    let mut record = StringRecord::new();
+    let mut count = 0;
+    while reader.read_record(&mut record)? {
+        let key: Vec<String> = if columns.is_empty() {
+            record.iter().map(String::from).collect()
+        } else {
+            columns
+                .iter()
+                .map(|&i| record.get(i).unwrap_or_default().to_string())
+                .collect()
+        };
+        if seen.insert(key) {
+            writer.write_record(&record)?;
+            count += 1;
+        }
+    }
+    writer.flush()?;
+    Ok(count)
+}
+
+// keep the input delimiter when the csv writer can express it
+pub(crate) fn csv_writer<W: Write>(writer: W, read: &CsvReadOptions) -> Writer<W> {
+    let delimiter = match read.delimiter.as_bytes() {
+        [b] => *b,
+        _ => b',',
+    };
+    WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &str = "\
+name,team,age
+Buffon,Juventus,41
+Perin,Juventus,26
+Szczesny,Juventus,29
+Donnarumma,Milan,20
+Pinsoglio,Juventus,29
+Handanovic,Inter,35
+";
+
+    fn sort(opts: &SortOptions) -> Result<String> {
+        let mut buf = Vec::new();
+        sort_csv(INPUT.as_bytes(), &mut buf, &CsvReadOptions::default(), opts)?;
+        Ok(String::from_utf8(buf)?)
+    }
+
+    #[test]
+    fn test_sort_csv_in_memory_and_external() -> Result<()> {
+        let expected = "\
+name,team,age
+Donnarumma,Milan,20
+Perin,Juventus,26
+Szczesny,Juventus,29
+Pinsoglio,Juventus,29
+Handanovic,Inter,35
+Buffon,Juventus,41
+";
+        for buffer_rows in [100, 4, 1] {
+            let opts = SortOptions {
+                by: vec!["age".into()],
+                numeric: true,
+                buffer_rows,
+                ..Default::default()
+            };
+            assert_eq!(sort(&opts)?, expected, "buffer_rows = {}", buffer_rows);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_sort_csv_descending_by_two_columns() -> Result<()> {
+        let opts = SortOptions {
+            by: vec!["team".into(), "name".into()],
+            descending: true,
+            buffer_rows: 2,
+            ..Default::default()
+        };
+        let ret = sort(&opts)?;
+        let names: Vec<&str> = ret
+            .lines()
+            .skip(1)
+            .map(|l| l.split(',').next().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "Donnarumma",
+                "Szczesny",
+                "Pinsoglio",
+                "Perin",
+                "Buffon",
+                "Handanovic"
+            ]
+        );
+
+        // non-numeric values stay last when descending
+        let opts = SortOptions {
+            by: vec!["a".into()],
+            descending: true,
+            numeric: true,
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        let input = "a\nx\n3\n10\n";
+        sort_csv(
+            input.as_bytes(),
+            &mut buf,
+            &CsvReadOptions::default(),
+            &opts,
+        )?;
+        assert_eq!(String::from_utf8(buf)?, "a\n10\n3\nx\n");
+
+        assert!(sort(&SortOptions {
+            by: vec!["missing".into()],
+            ..Default::default()
+        })
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_dedup_csv() -> Result<()> {
+        let mut buf = Vec::new();
+        let count = dedup_csv(
+            INPUT.as_bytes(),
+            &mut buf,
+            &CsvReadOptions::default(),
+            &["team".into()],
+        )?;
+        assert_eq!(count, 3);
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "name,team,age\nBuffon,Juventus,41\nDonnarumma,Milan,20\nHandanovic,Inter,35\n"
+        );
+
+        let input = "a,b\n1,2\n1,2\n1,3\n";
+        let mut buf = Vec::new();
+        let count = dedup_csv(input.as_bytes(), &mut buf, &CsvReadOptions::default(), &[])?;
+        assert_eq!(count, 2);
+        Ok(())
+    }
+}
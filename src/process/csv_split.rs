@@ -0,0 +1,345 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{File, OpenOptions},
+    io::{BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use super::{csv_filter::column_index, csv_sort::csv_writer, CsvReadOptions, CsvRecords};
+use anyhow::{anyhow, Result};
+use csv::{StringRecord, Writer};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SplitBy {
+    // at most this many records per file
+    Rows(usize),
+    // one file per distinct value of the column
+    Column(String),
+}
+
+// files kept open at once when splitting by a column, the least recently
+// used one is closed beyond that and reopened for appending when needed
+const MAX_OPEN_FILES: usize = 64;
+
+/// Splits a csv input into files named `<prefix>_<n>.csv` when splitting by
+/// rows, or `<prefix>_<value>.csv` when splitting by a column, in `dir`.
+/// Every file starts with the header of the input. Returns the files written
+/// in the order they were created.
+pub fn split_csv(
+    reader: impl Read,
+    read: &CsvReadOptions,
+    by: &SplitBy,
+    dir: &Path,
+    prefix: &str,
+) -> Result<Vec<PathBuf>> {
+    split_with_limit(reader, read, by, dir, prefix, MAX_OPEN_FILES)
+}
+
+fn split_with_limit(
+    reader: impl Read,
+    read: &CsvReadOptions,
+    by: &SplitBy,
+    dir: &Path,
+    prefix: &str,
+    max_open: usize,
+) -> Result<Vec<PathBuf>> {
+    let mut reader = CsvRecords::new(reader, read)?;
+    let headers = reader.headers().clone();
+    let mut files = Vec::new();
+    let path = |name: &str| dir.join(format!("{}_{}.csv", prefix, name));
+    // only new files get the header
+    let open = |path: &Path, append: bool| -> Result<Writer<BufWriter<File>>> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)?;
+        let mut writer = csv_writer(BufWriter::new(file), read);
+        if read.has_headers && !append {
+            writer.write_record(&headers)?;
+        }
+        Ok(writer)
+    };
+
+    let mut record = StringRecord::new();
+    match by {
+        SplitBy::Rows(0) => return Err(anyhow!("Rows per file must be at least 1")),
+        SplitBy::Rows(rows) => {
+            let mut writer: Option<Writer<_>> = None;
+            let mut count = 0;
+            while reader.read_record(&mut record)? {
+                if count % rows == 0 {
+                    if let Some(mut w) = writer.take() {
+                        w.flush()?;
+                    }
+                    let path = path(&(count / rows + 1).to_string());
+                    writer = Some(open(&path, false)?);
+                    files.push(path);
+                }
+                if let Some(w) = writer.as_mut() {
+                    w.write_record(&record)?;
+                }
+                count += 1;
+            }
+            if let Some(mut w) = writer {
+                w.flush()?;
+            }
+        }
+        SplitBy::Column(name) => {
+            let column = column_index(&headers, name)?;
+            let mut paths: HashMap<String, PathBuf> = HashMap::new();
+            let mut names = HashSet::new();
+            // least recently used first
+            let mut writers: Vec<(String, Writer<_>)> = Vec::new();
+            while reader.read_record(&mut record)? {
+                let value = record.get(column).unwrap_or_default();
+                let (value, mut writer) = match writers.iter().rposition(|(v, _)| v == value) {
+                    Some(i) => writers.remove(i),
+                    None => {
+                        if writers.len() >= max_open.max(1) {
+                            writers.remove(0).1.flush()?;
+                        }
+                        let writer = match paths.get(value) {
+                            Some(path) => open(path, true)?,
+                            None => {
+                                let path = path(&unique_name(file_name(value), &mut names));
+                                let writer = open(&path, false)?;
+                                paths.insert(value.to_string(), path.clone());
+                                files.push(path);
+                                writer
+                            }
+                        };
+                        (value.to_string(), writer)
+                    }
+                };
+                writer.write_record(&record)?;
+                writers.push((value, writer));
+            }
+            for (_, w) in writers.iter_mut() {
+                w.flush()?;
+            }
+        }
+    }
+    Ok(files)
+}
+
+// keeps letters, digits, `-` and `.` so values are safe as file names
+fn file_name(value: &str) -> String {
+    let name: String = value
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    match name.trim_matches('.') {
+        "" => "empty".to_string(),
+        name => name.to_string(),
+    }
+}
+
+// values mapping to the same file name, like `a b` and `a/b`, get a suffix
+fn unique_name(name: String, names: &mut HashSet<String>) -> String {
+    let mut candidate = name.clone();
+    let mut n = 1;
+    while !names.insert(candidate.clone()) {
+        n += 1;
+        candidate = format!("{}_{}", name, n);
+    }
+    candidate
+}
+
+/// Concatenates csv inputs into one. Later inputs may list the columns in a
+/// different order and are rearranged to match the first one, but must have
+/// exactly the same columns. Without headers inputs need the same number of
+/// columns. Returns the number of records written.
+pub fn merge_csv<R: Read>(
+    inputs: impl IntoIterator<Item = (String, R)>,
+    writer: impl Write,
+    read: &CsvReadOptions,
+) -> Result<usize> {
+    let mut writer = csv_writer(writer, read);
+    let mut headers: Option<(String, StringRecord)> = None;
+    let mut record = StringRecord::new();
+    let mut count = 0;
+    for (name, input) in inputs {
+        let mut reader = CsvRecords::new(input, read)?;
+        let order = match &headers {
+            None => {
+                if read.has_headers {
+                    writer.write_record(reader.headers())?;
+                }
+                headers = Some((name, reader.headers().clone()));
+                None
+            }
+            Some((first, expected)) => column_order(expected, reader.headers(), read.has_headers)
+                .map_err(|e| {
+                anyhow!("{} doesn't match the columns of {}: {}", name, first, e)
+            })?,
+        };
+        while reader.read_record(&mut record)? {
+            match &order {
+                Some(order) => writer.write_record(order.iter().map(|&i| &record[i]))?,
+                None => writer.write_record(&record)?,
+            }
+            count += 1;
+        }
+    }
+    writer.flush()?;
+    Ok(count)
+}
+
+// positions of the expected columns in `actual`, None when already in order
+fn column_order(
+    expected: &StringRecord,
+    actual: &StringRecord,
+    has_headers: bool,
+) -> Result<Option<Vec<usize>>> {
+    if !has_headers || expected == actual {
+        if expected.len() != actual.len() {
+            return Err(anyhow!(
+                "expected {} columns, found {}",
+                expected.len(),
+                actual.len()
+            ));
+        }
+        return Ok(None);
+    }
+    let missing: Vec<_> = expected
+        .iter()
+        .filter(|c| !actual.iter().any(|a| a == *c))
+        .collect();
+    let unexpected: Vec<_> = actual
+        .iter()
+        .filter(|c| !expected.iter().any(|e| e == *c))
+        .collect();
+    if !missing.is_empty() || !unexpected.is_empty() {
+        return Err(anyhow!(
+            "missing columns [{}], unexpected columns [{}]",
+            missing.join(", "),
+            unexpected.join(", ")
+        ));
+    }
+    if actual.len() != expected.len() {
+        return Err(anyhow!("duplicate column names"));
+    }
+    Ok(Some(
+        expected
+            .iter()
+            .map(|c| column_index(actual, c))
+            .collect::<Result<_>>()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_split_by_rows_and_merge_back() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let files = split_csv(
+            File::open("assets/juventus.csv")?,
+            &CsvReadOptions::default(),
+            &SplitBy::Rows(10),
+            dir.path(),
+            "juventus",
+        )?;
+        assert_eq!(files.len(), 3);
+        assert!(files[2].ends_with("juventus_3.csv"));
+        let header = "Name,Position,DOB,Nationality,Kit Number\n";
+        for file in &files {
+            assert!(fs::read_to_string(file)?.starts_with(header));
+        }
+
+        let inputs = files
+            .iter()
+            .map(|f| Ok((f.display().to_string(), File::open(f)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let mut buf = Vec::new();
+        let count = merge_csv(inputs, &mut buf, &CsvReadOptions::default())?;
+        assert_eq!(count, 27);
+        assert_eq!(buf, fs::read("assets/juventus.csv")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_by_column() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = "team,name\nA/B,x\nA B,y\n,z\nA/B,w\n";
+        let files = split_csv(
+            input.as_bytes(),
+            &CsvReadOptions::default(),
+            &SplitBy::Column("team".into()),
+            dir.path(),
+            "teams",
+        )?;
+        let names: Vec<_> = files
+            .iter()
+            .map(|f| f.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            ["teams_A_B.csv", "teams_A_B_2.csv", "teams_empty.csv"]
+        );
+        assert_eq!(fs::read_to_string(&files[0])?, "team,name\nA/B,x\nA/B,w\n");
+        assert!(split_csv(
+            input.as_bytes(),
+            &CsvReadOptions::default(),
+            &SplitBy::Column("club".into()),
+            dir.path(),
+            "teams",
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_by_column_beyond_open_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut input = String::from("id,n\n");
+        for n in 0..20 {
+            input.push_str(&format!("{},{}\n", n % 7, n));
+        }
+        let files = split_with_limit(
+            input.as_bytes(),
+            &CsvReadOptions::default(),
+            &SplitBy::Column("id".into()),
+            dir.path(),
+            "ids",
+            2,
+        )?;
+        assert_eq!(files.len(), 7);
+        assert_eq!(
+            fs::read_to_string(dir.path().join("ids_3.csv"))?,
+            "id,n\n3,3\n3,10\n3,17\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_reorders_and_rejects_mismatches() -> Result<()> {
+        let merge = |inputs: &[&'static str]| -> Result<String> {
+            let inputs = inputs
+                .iter()
+                .enumerate()
+                .map(|(i, s)| (format!("input{}", i + 1), s.as_bytes()));
+            let mut buf = Vec::new();
+            merge_csv(inputs, &mut buf, &CsvReadOptions::default())?;
+            Ok(String::from_utf8(buf)?)
+        };
+        assert_eq!(merge(&["a,b\n1,2\n", "b,a\n4,3\n"])?, "a,b\n1,2\n3,4\n");
+        let err = merge(&["a,b\n1,2\n", "a,c\n3,4\n"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "input2 doesn't match the columns of input1: missing columns [b], unexpected columns [c]"
+        );
+        assert!(merge(&["a,b\n1,2\n", "a,b,a\n1,2,3\n"]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,253 @@
+use std::{collections::HashMap, fmt, io::Read, io::Write, str::FromStr};
+
+use super::{csv_filter::column_index, csv_output::record_writer, CsvReadOptions, CsvRecords};
+use crate::cli::OutputFormat;
+use anyhow::{anyhow, Result};
+use csv::StringRecord;
+use serde_json::{Map, Number, Value};
+
+// --agg sum:Salary,avg:Age,count
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggregateFn {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+/// An aggregation over a column, `count` without a column counts rows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Aggregate {
+    pub func: AggregateFn,
+    pub column: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Accumulator {
+    // non-empty cells
+    count: usize,
+    // cells that parse as numbers, what sum and avg are over
+    numeric: usize,
+    sum: f64,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+impl Accumulator {
+    fn add(&mut self, field: &str) {
+        if field.is_empty() {
+            return;
+        }
+        self.count += 1;
+        if let Ok(v) = field.trim().parse::<f64>() {
+            self.numeric += 1;
+            self.sum += v;
+            self.min = Some(self.min.map_or(v, |m| m.min(v)));
+            self.max = Some(self.max.map_or(v, |m| m.max(v)));
+        }
+    }
+}
+
+/// Groups records by the `group_by` columns (in first seen order) and
+/// computes the aggregates for every group; without `group_by` the whole
+/// input is one group. Returns the number of groups written.
+pub fn csv_stats(
+    reader: impl Read,
+    writer: impl Write,
+    read: &CsvReadOptions,
+    format: OutputFormat,
+    group_by: &[String],
+    aggregates: &[Aggregate],
+) -> Result<usize> {
+    let mut reader = CsvRecords::new(reader, read)?;
+    let headers = reader.headers().clone();
+    let group_columns = group_by
+        .iter()
+        .map(|name| column_index(&headers, name))
+        .collect::<Result<Vec<_>>>()?;
+    let agg_columns = aggregates
+        .iter()
+        .map(|agg| {
+            agg.column
+                .as_ref()
+                .map(|c| column_index(&headers, c))
+                .transpose()
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut groups: Vec<(Vec<String>, usize, Vec<Accumulator>)> = Vec::new();
+    let mut index: HashMap<Vec<String>, usize> = HashMap::new();
+    let mut record = StringRecord::new();
+    while reader.read_record(&mut record)? {
+        let key: Vec<String> = group_columns
+            .iter()
+            .map(|&i| record.get(i).unwrap_or_default().to_string())
+            .collect();
+        let idx = *index.entry(key.clone()).or_insert_with(|| {
+            groups.push((key, 0, vec![Accumulator::default(); aggregates.len()]));
+            groups.len() - 1
+        });
+        let (_, rows, accs) = &mut groups[idx];
+        *rows += 1;
+        for (acc, column) in accs.iter_mut().zip(&agg_columns) {
+            if let Some(i) = column {
+                acc.add(record.get(*i).unwrap_or_default());
+            }
+        }
+    }
+
+    let mut writer = record_writer(writer, format, b',');
+    for (key, rows, accs) in &groups {
+        let mut row = Map::new();
+        for (name, value) in group_by.iter().zip(key) {
+            row.insert(name.clone(), Value::String(value.clone()));
+        }
+        for (agg, acc) in aggregates.iter().zip(accs) {
+            let value = match (agg.func, &agg.column) {
+                (AggregateFn::Count, None) => Value::from(*rows),
+                (AggregateFn::Count, Some(_)) => Value::from(acc.count),
+                (AggregateFn::Sum, _) => number_value(acc.sum),
+                (AggregateFn::Avg, _) => match acc.numeric {
+                    0 => Value::Null,
+                    n => number_value(acc.sum / n as f64),
+                },
+                (AggregateFn::Min, _) => acc.min.map_or(Value::Null, number_value),
+                (AggregateFn::Max, _) => acc.max.map_or(Value::Null, number_value),
+            };
+            row.insert(agg.to_string(), value);
+        }
+        writer.write_record(&Value::Object(row))?;
+    }
+    writer.finish()?;
+    Ok(groups.len())
+}
+
+// whole numbers are shown without a trailing `.0`
+pub(crate) fn number_value(v: f64) -> Value {
+    if v.fract() == 0.0 && v.abs() < i64::MAX as f64 {
+        Value::from(v as i64)
+    } else {
+        Number::from_f64(v).map_or(Value::Null, Value::Number)
+    }
+}
+
+impl FromStr for Aggregate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (func, column) = match s.split_once(':') {
+            Some((func, column)) => (func, Some(column.to_string())),
+            None => (s, None),
+        };
+        let func = match func {
+            "count" => AggregateFn::Count,
+            "sum" => AggregateFn::Sum,
+            "avg" | "mean" => AggregateFn::Avg,
+            "min" => AggregateFn::Min,
+            "max" => AggregateFn::Max,
+            _ => return Err(anyhow!("Invalid aggregate function: {}", func)),
+        };
+        if column.is_none() && func != AggregateFn::Count {
+            return Err(anyhow!("{} needs a column, e.g. {}:Age", s, s));
+        }
+        Ok(Aggregate { func, column })
+    }
+}
+
+impl From<AggregateFn> for &'static str {
+    fn from(value: AggregateFn) -> Self {
+        match value {
+            AggregateFn::Count => "count",
+            AggregateFn::Sum => "sum",
+            AggregateFn::Avg => "avg",
+            AggregateFn::Min => "min",
+            AggregateFn::Max => "max",
+        }
+    }
+}
+
+impl fmt::Display for Aggregate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let func: &str = self.func.into();
+        match &self.column {
+            Some(column) => write!(f, "{}({})", func, column),
+            None => write!(f, "{}", func),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_csv_stats() -> Result<()> {
+        let input = "\
+name,team,age,salary
+Buffon,Juventus,41,2.5
+Perin,Juventus,26,
+Donnarumma,Milan,20,6
+Szczesny,Juventus,29,7
+";
+        let aggregates: Vec<Aggregate> =
+            ["count", "sum:salary", "avg:age", "min:age", "max:salary"]
+                .iter()
+                .map(|s| s.parse())
+                .collect::<Result<_>>()?;
+        let mut buf = Vec::new();
+        let groups = csv_stats(
+            input.as_bytes(),
+            &mut buf,
+            &CsvReadOptions::default(),
+            OutputFormat::Json,
+            &["team".into()],
+            &aggregates,
+        )?;
+        assert_eq!(groups, 2);
+        let ret: Value = serde_json::from_slice(&buf)?;
+        assert_eq!(
+            ret,
+            json!([
+                {"team": "Juventus", "count": 3, "sum(salary)": 9.5, "avg(age)": 32, "min(age)": 26, "max(salary)": 7},
+                {"team": "Milan", "count": 1, "sum(salary)": 6, "avg(age)": 20, "min(age)": 20, "max(salary)": 6},
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_avg_skips_non_numeric_cells() -> Result<()> {
+        let aggregates: Vec<Aggregate> = ["count:age", "avg:age"]
+            .iter()
+            .map(|s| s.parse())
+            .collect::<Result<_>>()?;
+        let mut buf = Vec::new();
+        csv_stats(
+            "age\n41\nx\n\ny\n".as_bytes(),
+            &mut buf,
+            &CsvReadOptions::default(),
+            OutputFormat::Json,
+            &[],
+            &aggregates,
+        )?;
+        let ret: Value = serde_json::from_slice(&buf)?;
+        assert_eq!(ret, json!([{"count(age)": 3, "avg(age)": 41}]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_aggregate() {
+        assert_eq!(
+            "avg:Kit Number".parse::<Aggregate>().unwrap(),
+            Aggregate {
+                func: AggregateFn::Avg,
+                column: Some("Kit Number".into())
+            }
+        );
+        assert!("sum".parse::<Aggregate>().is_err());
+        assert!("median:Age".parse::<Aggregate>().is_err());
+    }
+}
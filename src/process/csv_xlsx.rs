@@ -0,0 +1,129 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use calamine::{open_workbook_auto, Data, Reader};
+use csv::WriterBuilder;
+
+const SPREADSHEET_EXTENSIONS: [&str; 5] = ["xlsx", "xlsm", "xlsb", "xls", "ods"];
+
+pub fn is_spreadsheet(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| SPREADSHEET_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Reads a sheet of a workbook as comma separated csv, so it can go through
+/// the regular csv pipeline. `sheet` is a sheet name or a zero-based index,
+/// the first sheet is used when omitted.
+pub fn read_sheet(path: &str, sheet: Option<&str>) -> Result<Vec<u8>> {
+    let mut workbook = open_workbook_auto(path)?;
+    let names = workbook.sheet_names();
+    let name = match sheet {
+        None => names.first(),
+        Some(sheet) => names
+            .iter()
+            .find(|n| *n == sheet)
+            .or_else(|| sheet.parse::<usize>().ok().and_then(|i| names.get(i))),
+    }
+    .ok_or_else(|| {
+        anyhow!(
+            "Sheet {:?} not found, available sheets: {}",
+            sheet.unwrap_or_default(),
+            names.join(", ")
+        )
+    })?
+    .clone();
+    let range = workbook.worksheet_range(&name)?;
+
+    let mut writer = WriterBuilder::new().flexible(true).from_writer(Vec::new());
+    for row in range.rows() {
+        writer.write_record(row.iter().map(cell_text))?;
+    }
+    Ok(writer.into_inner()?)
+}
+
+fn cell_text(cell: &Data) -> String {
+    match cell {
+        Data::Empty => String::new(),
+        Data::String(s) | Data::DateTimeIso(s) | Data::DurationIso(s) => s.clone(),
+        Data::Int(i) => i.to_string(),
+        // excel stores every number as a float, show whole ones as integers
+        Data::Float(f) if f.fract() == 0.0 && f.abs() < 1e15 => (*f as i64).to_string(),
+        Data::Float(f) => f.to_string(),
+        Data::Bool(b) => b.to_string(),
+        Data::DateTime(dt) if dt.is_datetime() => {
+            let (y, mo, d, h, mi, s, ms) = dt.to_ymd_hms_milli();
+            match (h, mi, s, ms) {
+                (0, 0, 0, 0) => format!("{:04}-{:02}-{:02}", y, mo, d),
+                (_, _, _, 0) => format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}", y, mo, d, h, mi, s),
+                _ => format!(
+                    "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}",
+                    y, mo, d, h, mi, s, ms
+                ),
+            }
+        }
+        Data::DateTime(dt) => dt.as_f64().to_string(),
+        Data::Error(e) => e.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cli::OutputFormat, convert_csv, CsvConvertOptions};
+    use rust_xlsxwriter::Workbook;
+    use std::fs::File;
+
+    // copies a csv file into the second sheet of a new workbook, numbers as numbers
+    fn csv_to_xlsx(input: &str, path: &Path) -> Result<()> {
+        let mut workbook = Workbook::new();
+        workbook
+            .add_worksheet()
+            .set_name("Notes")?
+            .write(0, 0, "n/a")?;
+        let sheet = workbook.add_worksheet().set_name("Players")?;
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_path(input)?;
+        for (row, record) in reader.records().enumerate() {
+            for (col, cell) in record?.iter().enumerate() {
+                let (row, col) = (row as u32, col as u16);
+                match cell.parse::<f64>() {
+                    Ok(n) => sheet.write_number(row, col, n)?,
+                    Err(_) => sheet.write_string(row, col, cell)?,
+                };
+            }
+        }
+        workbook.save(path)?;
+        Ok(())
+    }
+
+    fn to_json(reader: impl std::io::Read) -> Result<serde_json::Value> {
+        let mut buf = Vec::new();
+        let opts = CsvConvertOptions {
+            infer_types: true,
+            ..Default::default()
+        };
+        convert_csv(reader, &mut buf, OutputFormat::Json, &opts)?;
+        Ok(serde_json::from_slice(&buf)?)
+    }
+
+    #[test]
+    fn test_xlsx_round_trip() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("juventus.xlsx");
+        csv_to_xlsx("assets/juventus.csv", &path)?;
+        let path = path.to_str().unwrap();
+        assert!(is_spreadsheet(path));
+
+        let expected = to_json(File::open("assets/juventus.csv")?)?;
+        for sheet in ["Players", "1"] {
+            let csv = read_sheet(path, Some(sheet))?;
+            assert_eq!(to_json(csv.as_slice())?, expected);
+        }
+        assert_eq!(read_sheet(path, None)?, b"n/a\n");
+        assert!(read_sheet(path, Some("Contracts")).is_err());
+        Ok(())
+    }
+}
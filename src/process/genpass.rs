@@ -1,7 +1,217 @@
-use anyhow::Result;
-use rand::{seq::SliceRandom, thread_rng};
+use std::{collections::HashSet, fmt, io::Write, str::FromStr};
+
+use anyhow::{anyhow, Result};
+use rand::{rngs::OsRng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use serde::Serialize;
 use zxcvbn::zxcvbn;
 
+// look-alikes like 0/O, 1/l/I are left out of the default sets
+const UPPER: &str = "ABCDEFGHJKLMNPQRSTUVWXYZ";
+const LOWER: &str = "abcdefghijkmnopqrstuvwxyz";
+const NUMBER: &str = "123456789";
+const SYMBOL: &str = "!@#$%&*,.<>?";
+
+const FULL_UPPER: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const FULL_LOWER: &str = "abcdefghijklmnopqrstuvwxyz";
+const FULL_NUMBER: &str = "0123456789";
+const FULL_SYMBOL: &str = "!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~";
+
+const CONSONANTS: &str = "bcdfghjklmnpqrstvwxyz";
+const VOWELS: &str = "aeiou";
+const PLACEHOLDERS: &str = "CcVvAa9!*";
+
+// whole passwords drawn before falling back to placing required characters first
+const MAX_ATTEMPTS: usize = 1000;
+
+// 7776 words, one per roll of five dice
+const WORDLIST: &str = include_str!("../../assets/wordlist.txt");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Charset {
+    // letters, digits and a few symbols without look-alikes
+    Default,
+    // every printable ASCII character
+    Full,
+    Hex,
+    // the characters given in `PasswordPolicy::chars`
+    Custom,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RngKind {
+    // the operating system generator, the default
+    Os,
+    // ChaCha20, seeded from the OS or a fixed seed for reproducible output
+    Chacha,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecretFormat {
+    // one password per line
+    Text,
+    Json,
+    Csv,
+    // `NAME=value` lines for a `.env` file
+    Env,
+}
+
+/// A generated password with its strength, as written by `write_secrets`.
+#[derive(Debug, Clone, Serialize)]
+pub struct GeneratedSecret {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub password: String,
+    // in characters
+    pub length: usize,
+    // bits, from the size of the alphabet or wordlist
+    pub entropy: f64,
+    // zxcvbn score from 0 to 4
+    pub score: u8,
+}
+
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    pub length: usize,
+    pub charset: Charset,
+    // alphabet of the custom charset, sorted into classes by character type
+    pub chars: String,
+    pub upper: bool,
+    pub lower: bool,
+    pub number: bool,
+    pub symbol: bool,
+    // replaces the symbols of the charset
+    pub symbols: Option<String>,
+    // characters never used, e.g. `0O1lI`
+    pub exclude: String,
+    // minimum characters of each class, one for every enabled class by default
+    pub min_upper: Option<usize>,
+    pub min_lower: Option<usize>,
+    pub min_digits: Option<usize>,
+    pub min_symbols: Option<usize>,
+    // use every character at most once
+    pub no_repeat: bool,
+    // fixed structure instead of the classes above, only `exclude` and
+    // `symbols` apply to it
+    pub pattern: Option<PasswordPattern>,
+}
+
+/// A password template like `Cvccvc-9999-!!`, each placeholder is replaced
+/// with a random character of its class and anything else is kept:
+///
+/// - `C`/`c`: upper/lowercase consonant, `V`/`v`: upper/lowercase vowel
+/// - `A`/`a`: upper/lowercase letter, `9`: digit, `!`: symbol
+/// - `*`: letter or digit
+///
+/// A backslash keeps the next character as it is, e.g. `\9` for a literal `9`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasswordPattern {
+    slots: Vec<PatternSlot>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PatternSlot {
+    Literal(char),
+    Placeholder(char),
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            length: 16,
+            charset: Charset::Default,
+            chars: String::new(),
+            upper: true,
+            lower: true,
+            number: true,
+            symbol: true,
+            symbols: None,
+            exclude: String::new(),
+            min_upper: None,
+            min_lower: None,
+            min_digits: None,
+            min_symbols: None,
+            no_repeat: false,
+            pattern: None,
+        }
+    }
+}
+
+impl PasswordPattern {
+    /// Alternating consonants and vowels, `cvcv...`, which read as syllables.
+    pub fn pronounceable(length: usize) -> Self {
+        let slots = (0..length)
+            .map(|i| PatternSlot::Placeholder(if i % 2 == 0 { 'c' } else { 'v' }))
+            .collect();
+        Self { slots }
+    }
+
+    // the characters each slot draws from, a single one for literals
+    fn alphabets(&self, policy: &PasswordPolicy) -> Result<Vec<Vec<char>>> {
+        if !self
+            .slots
+            .iter()
+            .any(|s| matches!(s, PatternSlot::Placeholder(_)))
+        {
+            return Err(anyhow!("A pattern needs at least one placeholder"));
+        }
+        self.slots
+            .iter()
+            .map(|slot| {
+                let placeholder = match slot {
+                    PatternSlot::Literal(c) => return Ok(vec![*c]),
+                    PatternSlot::Placeholder(c) => *c,
+                };
+                let chars: String = match placeholder {
+                    'C' => CONSONANTS.to_uppercase(),
+                    'c' => CONSONANTS.into(),
+                    'V' => VOWELS.to_uppercase(),
+                    'v' => VOWELS.into(),
+                    'A' => FULL_UPPER.into(),
+                    'a' => FULL_LOWER.into(),
+                    '9' => FULL_NUMBER.into(),
+                    '!' => policy.symbols.as_deref().unwrap_or(SYMBOL).into(),
+                    // `*`
+                    _ => [FULL_UPPER, FULL_LOWER, FULL_NUMBER].concat(),
+                };
+                let mut allowed = Vec::new();
+                for c in chars.chars() {
+                    if !policy.exclude.contains(c) && !allowed.contains(&c) {
+                        allowed.push(c);
+                    }
+                }
+                if allowed.is_empty() {
+                    return Err(anyhow!(
+                        "No characters left for `{}` in the pattern",
+                        placeholder
+                    ));
+                }
+                Ok(allowed)
+            })
+            .collect()
+    }
+}
+
+impl GeneratedSecret {
+    pub fn new(name: Option<String>, password: String, entropy: f64) -> Self {
+        let score = zxcvbn(&password, &[]).score().into();
+        Self {
+            name,
+            length: password.chars().count(),
+            password,
+            entropy: (entropy * 100.0).round() / 100.0,
+            score,
+        }
+    }
+}
+
+// a character class with the characters left after exclusions
+struct CharClass {
+    name: &'static str,
+    chars: Vec<char>,
+    min: usize,
+}
+
 pub fn generate_password(
     length: u8,
     upper: bool,
@@ -9,50 +219,846 @@ pub fn generate_password(
     number: bool,
     symbol: bool,
 ) -> Result<String> {
-    const UPPER: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ";
-    const LOWER: &[u8] = b"abcdefghijkmnopqrstuvwxyz";
-    const NUMBER: &[u8] = b"123456789";
-    const SYMBOL: &[u8] = b"!@#$%&*,.<>?";
+    generate_with_policy(&PasswordPolicy {
+        length: length as usize,
+        upper,
+        lower,
+        number,
+        symbol,
+        ..Default::default()
+    })
+}
+
+/// Generates a password meeting the policy with the OS random number generator.
+pub fn generate_with_policy(policy: &PasswordPolicy) -> Result<String> {
+    generate_with_rng(policy, &mut OsRng)
+}
+
+/// Generates a password meeting the policy. Every character is drawn
+/// uniformly from the enabled alphabet and passwords missing the minimum of
+/// a class are drawn again, so all passwords meeting the policy are equally
+/// likely. Policies too strict to be met by chance get their required
+/// characters drawn from each class first, then shuffled.
+pub fn generate_with_rng(policy: &PasswordPolicy, rng: &mut dyn RngCore) -> Result<String> {
+    if let Some(pattern) = &policy.pattern {
+        return Ok(pattern
+            .alphabets(policy)?
+            .iter()
+            .map(|chars| chars[uniform_index(rng, chars.len())])
+            .collect());
+    }
+    let classes = char_classes(policy)?;
+    let alphabet = alphabet(&classes);
+    if alphabet.is_empty() {
+        return Err(anyhow!("No characters left to generate a password from"));
+    }
+    let required: usize = classes.iter().map(|class| class.min).sum();
+    if required > policy.length {
+        return Err(anyhow!(
+            "Length {} is too short, the policy needs at least {} characters",
+            policy.length,
+            required
+        ));
+    }
+    if policy.no_repeat {
+        if let Some(class) = classes.iter().find(|c| c.min > c.chars.len()) {
+            return Err(anyhow!(
+                "Only {} {} characters are available without repeats",
+                class.chars.len(),
+                class.name
+            ));
+        }
+        if policy.length > alphabet.len() {
+            return Err(anyhow!(
+                "Only {} characters are available without repeats",
+                alphabet.len()
+            ));
+        }
+    }
+
+    for _ in 0..MAX_ATTEMPTS {
+        let password = draw(&alphabet, policy.length, policy.no_repeat, rng);
+        let meets_minimums = classes
+            .iter()
+            .all(|class| password.iter().filter(|c| class.chars.contains(c)).count() >= class.min);
+        if meets_minimums {
+            return Ok(password.into_iter().collect());
+        }
+    }
+
+    let mut password = Vec::with_capacity(policy.length);
+    let mut used = HashSet::new();
+    let mut pick = |chars: &[char], password: &mut Vec<char>| -> Result<()> {
+        let candidates: Vec<char> = chars
+            .iter()
+            .filter(|c| !policy.no_repeat || !used.contains(*c))
+            .copied()
+            .collect();
+        if candidates.is_empty() {
+            return Err(anyhow!("Ran out of characters without repeats"));
+        }
+        let c = candidates[uniform_index(rng, candidates.len())];
+        used.insert(c);
+        password.push(c);
+        Ok(())
+    };
+    for class in &classes {
+        for _ in 0..class.min {
+            pick(&class.chars, &mut password)?;
+        }
+    }
+    while password.len() < policy.length {
+        pick(&alphabet, &mut password)?;
+    }
+    shuffle(&mut password, rng);
+    Ok(password.into_iter().collect())
+}
+
+/// Bits of entropy of a password drawn uniformly from the alphabet of the
+/// policy, `length * log2(alphabet)` or less without repeats. The minimums
+/// of the classes take a little off, which is not accounted for.
+pub fn password_entropy(policy: &PasswordPolicy) -> Result<f64> {
+    if let Some(pattern) = &policy.pattern {
+        return Ok(pattern
+            .alphabets(policy)?
+            .iter()
+            .map(|chars| (chars.len() as f64).log2())
+            .sum());
+    }
+    let n = alphabet(&char_classes(policy)?).len();
+    if policy.no_repeat {
+        return Ok((0..policy.length.min(n))
+            .map(|i| ((n - i) as f64).log2())
+            .sum());
+    }
+    Ok(policy.length as f64 * (n as f64).log2())
+}
+
+/// Writes the secrets in the given format. Json is an array of objects and
+/// csv has a header, both with the length, entropy and score of every
+/// password. Env needs every secret to have a name and writes the values in
+/// single quotes, or double quotes when they contain one.
+pub fn write_secrets(
+    secrets: &[GeneratedSecret],
+    mut writer: impl Write,
+    format: SecretFormat,
+) -> Result<()> {
+    match format {
+        SecretFormat::Text => {
+            for secret in secrets {
+                writeln!(writer, "{}", secret.password)?;
+            }
+        }
+        SecretFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, secrets)?;
+            writeln!(writer)?;
+        }
+        SecretFormat::Csv => {
+            let mut csv = csv::Writer::from_writer(&mut writer);
+            for secret in secrets {
+                csv.serialize(secret)?;
+            }
+            csv.flush()?;
+        }
+        SecretFormat::Env => {
+            for secret in secrets {
+                let name = secret
+                    .name
+                    .as_deref()
+                    .ok_or_else(|| anyhow!("Secrets in a .env file need a name"))?;
+                writeln!(writer, "{}={}", name, env_value(&secret.password))?;
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// single quotes keep `$`, `#` and backslashes literal in dotenv parsers and
+// shells, which don't allow escaping a single quote inside them
+fn env_value(value: &str) -> String {
+    if !value.contains('\'') {
+        return format!("'{}'", value);
+    }
+    let mut quoted = String::from('"');
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// A random number generator of the given kind, only ChaCha20 takes a seed.
+pub fn make_rng(kind: RngKind, seed: Option<u64>) -> Result<Box<dyn RngCore>> {
+    match (kind, seed) {
+        (RngKind::Os, None) => Ok(Box::new(OsRng)),
+        (RngKind::Os, Some(_)) => Err(anyhow!("A seed needs the chacha generator")),
+        (RngKind::Chacha, Some(seed)) => Ok(Box::new(ChaCha20Rng::seed_from_u64(seed))),
+        (RngKind::Chacha, None) => Ok(Box::new(ChaCha20Rng::from_rng(OsRng)?)),
+    }
+}
 
-    let mut password = Vec::new();
-    let mut chars = Vec::new();
-    let mut rng = thread_rng();
+// the characters of all classes, each once
+fn alphabet(classes: &[CharClass]) -> Vec<char> {
+    let mut alphabet: Vec<char> = Vec::new();
+    for c in classes.iter().flat_map(|class| &class.chars) {
+        if !alphabet.contains(c) {
+            alphabet.push(*c);
+        }
+    }
+    alphabet
+}
+
+// `len` characters of the alphabet, without repeats from a partial shuffle
+fn draw(alphabet: &[char], len: usize, no_repeat: bool, rng: &mut dyn RngCore) -> Vec<char> {
+    if no_repeat {
+        let mut chars = alphabet.to_vec();
+        for i in 0..len {
+            let j = i + uniform_index(rng, chars.len() - i);
+            chars.swap(i, j);
+        }
+        chars.truncate(len);
+        return chars;
+    }
+    (0..len)
+        .map(|_| alphabet[uniform_index(rng, alphabet.len())])
+        .collect()
+}
 
-    // make sure at least one character is included of each type
-    if upper {
-        chars.extend_from_slice(UPPER);
-        let c = UPPER.choose(&mut rng).expect("Chars won't be empty");
-        password.push(*c);
+/// A uniform index below `n`. Draws from the top of the u64 range that don't
+/// fill a whole block of `n` values are rejected, which is what keeps
+/// `x % n` free of modulo bias.
+pub fn uniform_index(rng: &mut dyn RngCore, n: usize) -> usize {
+    assert!(n > 0, "can't pick from an empty range");
+    let n = n as u64;
+    let zone = u64::MAX - (u64::MAX - n + 1) % n;
+    loop {
+        let x = rng.next_u64();
+        if x <= zone {
+            return (x % n) as usize;
+        }
     }
+}
 
-    if lower {
-        chars.extend_from_slice(LOWER);
-        let c = LOWER.choose(&mut rng).expect("Chars won't be empty");
-        password.push(*c);
+// Fisher-Yates
+fn shuffle<T>(items: &mut [T], rng: &mut dyn RngCore) {
+    for i in (1..items.len()).rev() {
+        items.swap(i, uniform_index(rng, i + 1));
     }
+}
+
+#[derive(Debug, Clone)]
+pub struct PassphraseOptions {
+    pub words: usize,
+    pub separator: String,
+    // uppercase the first letter of every word
+    pub capitalize: bool,
+}
 
-    if number {
-        chars.extend_from_slice(NUMBER);
-        let c = NUMBER.choose(&mut rng).expect("Chars won't be empty");
-        password.push(*c);
+impl Default for PassphraseOptions {
+    fn default() -> Self {
+        Self {
+            words: 6,
+            separator: "-".into(),
+            capitalize: false,
+        }
     }
+}
 
-    if symbol {
-        chars.extend_from_slice(SYMBOL);
-        let c = SYMBOL.choose(&mut rng).expect("Chars won't be empty");
-        password.push(*c);
+/// Words of a wordlist file, one per line. Dice numbers in front of the words
+/// like in the EFF lists (`11111 abacus`) are skipped, duplicates dropped.
+pub fn load_wordlist(text: &str) -> Result<Vec<&str>> {
+    let mut seen = HashSet::new();
+    let words: Vec<&str> = text
+        .lines()
+        .filter_map(|line| line.split_whitespace().last())
+        .filter(|word| seen.insert(*word))
+        .collect();
+    if words.len() < 2 {
+        return Err(anyhow!("A wordlist needs at least two distinct words"));
     }
+    Ok(words)
+}
 
-    for _ in 0..(length - password.len() as u8) {
-        let c = chars.choose(&mut rng).expect("Chars won't be empty");
-        password.push(*c);
+pub fn default_wordlist() -> Vec<&'static str> {
+    WORDLIST.lines().collect()
+}
+
+/// Draws words uniformly from the wordlist.
+pub fn generate_passphrase(
+    opts: &PassphraseOptions,
+    wordlist: &[&str],
+    rng: &mut dyn RngCore,
+) -> Result<String> {
+    if opts.words == 0 {
+        return Err(anyhow!("A passphrase needs at least one word"));
     }
+    if wordlist.is_empty() {
+        return Err(anyhow!("The wordlist is empty"));
+    }
+    let mut words = Vec::with_capacity(opts.words);
+    for _ in 0..opts.words {
+        let word = wordlist[uniform_index(rng, wordlist.len())];
+        words.push(if opts.capitalize {
+            capitalize(word)
+        } else {
+            word.to_string()
+        });
+    }
+    Ok(words.join(&opts.separator))
+}
 
-    password.shuffle(&mut rng);
-    let password = String::from_utf8(password)?;
-    println!("Password: {:?}", password);
-    let estimate = zxcvbn(&password, &[]);
-    eprintln!("{}", estimate.score());
+// bits of entropy of `words` words drawn from a list of `wordlist_len` words
+pub fn passphrase_entropy(words: usize, wordlist_len: usize) -> f64 {
+    words as f64 * (wordlist_len as f64).log2()
+}
 
-    Ok(password)
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn char_classes(policy: &PasswordPolicy) -> Result<Vec<CharClass>> {
+    let (upper, lower, number, symbol): (Vec<char>, Vec<char>, Vec<char>, Vec<char>) =
+        match policy.charset {
+            Charset::Default => (
+                UPPER.chars().collect(),
+                LOWER.chars().collect(),
+                NUMBER.chars().collect(),
+                SYMBOL.chars().collect(),
+            ),
+            Charset::Full => (
+                FULL_UPPER.chars().collect(),
+                FULL_LOWER.chars().collect(),
+                FULL_NUMBER.chars().collect(),
+                FULL_SYMBOL.chars().collect(),
+            ),
+            Charset::Hex => (
+                Vec::new(),
+                "abcdef".chars().collect(),
+                FULL_NUMBER.chars().collect(),
+                Vec::new(),
+            ),
+            Charset::Custom => {
+                if policy.chars.is_empty() {
+                    return Err(anyhow!("The custom charset needs the characters to use"));
+                }
+                let chars = policy.chars.chars().filter(|c| !c.is_whitespace());
+                (
+                    chars.clone().filter(|c| c.is_uppercase()).collect(),
+                    chars.clone().filter(|c| c.is_lowercase()).collect(),
+                    chars.clone().filter(|c| c.is_numeric()).collect(),
+                    chars
+                        .filter(|c| !c.is_uppercase() && !c.is_lowercase() && !c.is_numeric())
+                        .collect(),
+                )
+            }
+        };
+    let symbol = match &policy.symbols {
+        Some(symbols) => symbols.chars().collect(),
+        None => symbol,
+    };
+
+    let mut classes = Vec::new();
+    for (name, enabled, chars, min) in [
+        ("uppercase", policy.upper, upper, policy.min_upper),
+        ("lowercase", policy.lower, lower, policy.min_lower),
+        ("digit", policy.number, number, policy.min_digits),
+        ("symbol", policy.symbol, symbol, policy.min_symbols),
+    ] {
+        let mut allowed = Vec::new();
+        for c in chars {
+            if !policy.exclude.contains(c) && !allowed.contains(&c) {
+                allowed.push(c);
+            }
+        }
+        let min = match (enabled, allowed.is_empty(), min) {
+            (false, _, Some(n)) if n > 0 => {
+                return Err(anyhow!(
+                    "A minimum of {} {} characters needs them enabled",
+                    n,
+                    name
+                ))
+            }
+            (true, true, Some(n)) if n > 0 => {
+                return Err(anyhow!(
+                    "No {} characters left for a minimum of {}",
+                    name,
+                    n
+                ))
+            }
+            (false, _, _) | (_, true, _) => continue,
+            (true, false, min) => min.unwrap_or(1),
+        };
+        classes.push(CharClass {
+            name,
+            chars: allowed,
+            min,
+        });
+    }
+    Ok(classes)
+}
+
+impl FromStr for Charset {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(Charset::Default),
+            "full" => Ok(Charset::Full),
+            "hex" => Ok(Charset::Hex),
+            "custom" => Ok(Charset::Custom),
+            _ => Err(anyhow!("Invalid charset: {}", s)),
+        }
+    }
+}
+
+impl From<Charset> for &'static str {
+    fn from(charset: Charset) -> Self {
+        match charset {
+            Charset::Default => "default",
+            Charset::Full => "full",
+            Charset::Hex => "hex",
+            Charset::Custom => "custom",
+        }
+    }
+}
+
+impl fmt::Display for Charset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+impl FromStr for RngKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "os" => Ok(RngKind::Os),
+            "chacha" => Ok(RngKind::Chacha),
+            _ => Err(anyhow!("Invalid random number generator: {}", s)),
+        }
+    }
+}
+
+impl From<RngKind> for &'static str {
+    fn from(kind: RngKind) -> Self {
+        match kind {
+            RngKind::Os => "os",
+            RngKind::Chacha => "chacha",
+        }
+    }
+}
+
+impl fmt::Display for RngKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+impl FromStr for PasswordPattern {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut slots = Vec::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            slots.push(match c {
+                '\\' => PatternSlot::Literal(
+                    chars
+                        .next()
+                        .ok_or_else(|| anyhow!("Nothing to escape at the end of the pattern"))?,
+                ),
+                c if PLACEHOLDERS.contains(c) => PatternSlot::Placeholder(c),
+                c => PatternSlot::Literal(c),
+            });
+        }
+        if slots.is_empty() {
+            return Err(anyhow!("The pattern is empty"));
+        }
+        Ok(Self { slots })
+    }
+}
+
+impl FromStr for SecretFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(SecretFormat::Text),
+            "json" => Ok(SecretFormat::Json),
+            "csv" => Ok(SecretFormat::Csv),
+            "env" => Ok(SecretFormat::Env),
+            _ => Err(anyhow!("Invalid format: {}", s)),
+        }
+    }
+}
+
+impl From<SecretFormat> for &'static str {
+    fn from(format: SecretFormat) -> Self {
+        match format {
+            SecretFormat::Text => "text",
+            SecretFormat::Json => "json",
+            SecretFormat::Csv => "csv",
+            SecretFormat::Env => "env",
+        }
+    }
+}
+
+impl fmt::Display for SecretFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count(password: &str, f: fn(&char) -> bool) -> usize {
+        password.chars().filter(f).count()
+    }
+
+    #[test]
+    fn test_policy_minimums_and_exclusions() -> Result<()> {
+        let policy = PasswordPolicy {
+            length: 12,
+            charset: Charset::Full,
+            symbols: Some("!@#-_".into()),
+            exclude: "0O1lI".into(),
+            min_digits: Some(2),
+            min_symbols: Some(3),
+            ..Default::default()
+        };
+        for _ in 0..100 {
+            let password = generate_with_policy(&policy)?;
+            assert_eq!(password.chars().count(), 12);
+            assert!(count(&password, char::is_ascii_digit) >= 2);
+            assert!(count(&password, |c| "!@#-_".contains(*c)) >= 3);
+            assert!(count(&password, char::is_ascii_uppercase) >= 1);
+            assert!(!password.contains(|c| "0O1lI".contains(c)));
+            assert!(password
+                .chars()
+                .all(|c| c.is_alphanumeric() || "!@#-_".contains(c)));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_custom_charset_without_repeats() -> Result<()> {
+        let policy = PasswordPolicy {
+            length: 6,
+            charset: Charset::Custom,
+            chars: "abcXYZ12".into(),
+            no_repeat: true,
+            ..Default::default()
+        };
+        for _ in 0..100 {
+            let password = generate_with_policy(&policy)?;
+            let distinct: HashSet<char> = password.chars().collect();
+            assert_eq!(distinct.len(), 6);
+            assert!(password.chars().all(|c| "abcXYZ12".contains(c)));
+        }
+        let too_long = PasswordPolicy {
+            length: 9,
+            ..policy
+        };
+        assert!(generate_with_policy(&too_long).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_password_errors() {
+        let err = generate_password(16, false, false, false, false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "No characters left to generate a password from"
+        );
+        let err = generate_password(3, true, true, true, true).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Length 3 is too short, the policy needs at least 4 characters"
+        );
+        assert!(generate_password(0, true, false, false, false).is_err());
+        let password = generate_password(4, false, false, true, false).unwrap();
+        assert!(password.chars().all(|c| c.is_ascii_digit()));
+    }
+
+    #[test]
+    fn test_passphrase() -> Result<()> {
+        let wordlist = default_wordlist();
+        assert_eq!(wordlist.len(), 7776);
+        assert_eq!(load_wordlist(WORDLIST)?.len(), 7776);
+        assert!((passphrase_entropy(6, wordlist.len()) - 77.55).abs() < 0.01);
+        assert!(wordlist
+            .iter()
+            .all(|w| (3..=9).contains(&w.len()) && w.bytes().all(|b| b.is_ascii_lowercase())));
+
+        let opts = PassphraseOptions {
+            words: 5,
+            separator: ".".into(),
+            capitalize: true,
+        };
+        let passphrase = generate_passphrase(&opts, &wordlist, &mut OsRng)?;
+        let words: Vec<&str> = passphrase.split('.').collect();
+        assert_eq!(words.len(), 5);
+        for word in words {
+            assert!(word.starts_with(|c: char| c.is_ascii_uppercase()));
+            assert!(wordlist.contains(&word.to_lowercase().as_str()));
+        }
+
+        let custom = load_wordlist("11111\tapple\n11112\tpear\n\n11113\tapple\n")?;
+        assert_eq!(custom, ["apple", "pear"]);
+        assert!(load_wordlist("apple\n").is_err());
+        Ok(())
+    }
+
+    // Pearson's chi-squared statistic of the counts against a uniform distribution
+    fn chi_squared(counts: &[usize]) -> f64 {
+        let total: usize = counts.iter().sum();
+        let expected = total as f64 / counts.len() as f64;
+        counts
+            .iter()
+            .map(|&c| (c as f64 - expected).powi(2) / expected)
+            .sum()
+    }
+
+    // far above the 99.9% quantile for these degrees of freedom, the seeded
+    // generator makes the tests deterministic anyway
+    fn assert_uniform(counts: &[usize]) {
+        let df = (counts.len() - 1) as f64;
+        let stat = chi_squared(counts);
+        assert!(
+            stat < df + 6.0 * (2.0 * df).sqrt(),
+            "chi2 {} for {:?}",
+            stat,
+            counts
+        );
+    }
+
+    #[test]
+    fn test_uniform_index() {
+        let mut rng = make_rng(RngKind::Chacha, Some(7)).unwrap();
+        for n in [2, 7, 10, 62] {
+            let mut counts = vec![0; n];
+            for _ in 0..n * 2000 {
+                counts[uniform_index(&mut rng, n)] += 1;
+            }
+            assert_uniform(&counts);
+        }
+    }
+
+    #[test]
+    fn test_characters_are_uniform_within_each_class() -> Result<()> {
+        let policy = PasswordPolicy::default();
+        let classes = char_classes(&policy)?;
+        let mut rng = make_rng(RngKind::Chacha, Some(42))?;
+        let mut counts: Vec<Vec<usize>> = classes.iter().map(|c| vec![0; c.chars.len()]).collect();
+        for _ in 0..5000 {
+            let password = generate_with_rng(&policy, &mut rng)?;
+            for c in password.chars() {
+                for (class, counts) in classes.iter().zip(&mut counts) {
+                    if let Some(i) = class.chars.iter().position(|x| *x == c) {
+                        counts[i] += 1;
+                    }
+                }
+            }
+        }
+        for counts in &counts {
+            assert_uniform(counts);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_seeded_generation_is_reproducible() -> Result<()> {
+        let policy = PasswordPolicy::default();
+        let generate = |seed| -> Result<String> {
+            generate_with_rng(&policy, &mut make_rng(RngKind::Chacha, Some(seed))?)
+        };
+        assert_eq!(generate(1)?, generate(1)?);
+        assert_ne!(generate(1)?, generate(2)?);
+
+        let wordlist = default_wordlist();
+        let opts = PassphraseOptions::default();
+        let mut a = make_rng(RngKind::Chacha, Some(1))?;
+        let mut b = make_rng(RngKind::Chacha, Some(1))?;
+        assert_eq!(
+            generate_passphrase(&opts, &wordlist, &mut a)?,
+            generate_passphrase(&opts, &wordlist, &mut b)?
+        );
+        assert!(make_rng(RngKind::Os, Some(1)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_strict_policy_falls_back() -> Result<()> {
+        let policy = PasswordPolicy {
+            length: 10,
+            min_digits: Some(7),
+            min_symbols: Some(1),
+            ..Default::default()
+        };
+        let password = generate_with_rng(&policy, &mut make_rng(RngKind::Chacha, Some(3))?)?;
+        assert_eq!(password.chars().filter(char::is_ascii_digit).count(), 7);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unsatisfiable_policies() {
+        let policies = [
+            PasswordPolicy {
+                length: 3,
+                min_digits: Some(2),
+                ..Default::default()
+            },
+            PasswordPolicy {
+                symbol: false,
+                min_symbols: Some(1),
+                ..Default::default()
+            },
+            PasswordPolicy {
+                charset: Charset::Hex,
+                min_upper: Some(1),
+                ..Default::default()
+            },
+            PasswordPolicy {
+                charset: Charset::Custom,
+                ..Default::default()
+            },
+        ];
+        for policy in policies {
+            assert!(generate_with_policy(&policy).is_err(), "{:?}", policy);
+        }
+    }
+
+    #[test]
+    fn test_password_entropy() -> Result<()> {
+        // 24 + 25 + 9 + 12 characters
+        let entropy = password_entropy(&PasswordPolicy::default())?;
+        assert!((entropy - 16.0 * 70f64.log2()).abs() < 1e-9);
+        let hex = PasswordPolicy {
+            length: 8,
+            charset: Charset::Hex,
+            ..Default::default()
+        };
+        assert_eq!(password_entropy(&hex)?, 32.0);
+        let no_repeat = PasswordPolicy {
+            no_repeat: true,
+            ..hex
+        };
+        assert!(
+            (password_entropy(&no_repeat)? - (9..=16).map(|n| (n as f64).log2()).sum::<f64>())
+                .abs()
+                < 1e-9
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_secrets() -> Result<()> {
+        let secrets = vec![
+            GeneratedSecret::new(Some("DB_PASS".into()), "a$b#c".into(), 32.0),
+            GeneratedSecret::new(Some("API_KEY".into()), "it's".into(), 12.345),
+        ];
+        let write = |secrets: &[GeneratedSecret], format| -> Result<String> {
+            let mut buf = Vec::new();
+            write_secrets(secrets, &mut buf, format)?;
+            Ok(String::from_utf8(buf)?)
+        };
+        assert_eq!(
+            write(&secrets, SecretFormat::Env)?,
+            "DB_PASS='a$b#c'\nAPI_KEY=\"it's\"\n"
+        );
+        assert_eq!(write(&secrets, SecretFormat::Text)?, "a$b#c\nit's\n");
+
+        let csv = write(&secrets, SecretFormat::Csv)?;
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("name,password,length,entropy,score"));
+        assert_eq!(lines.next(), Some("DB_PASS,a$b#c,5,32.0,1"));
+
+        let json: serde_json::Value = serde_json::from_str(&write(&secrets, SecretFormat::Json)?)?;
+        assert_eq!(json[1]["name"], "API_KEY");
+        assert_eq!(json[1]["length"], 4);
+        assert_eq!(json[1]["entropy"], 12.35);
+
+        let unnamed = [GeneratedSecret::new(None, "x".into(), 1.0)];
+        assert!(write(&unnamed, SecretFormat::Env).is_err());
+        let json = write(&unnamed, SecretFormat::Json)?;
+        assert!(!json.contains("name"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_pattern() -> Result<()> {
+        let policy = PasswordPolicy {
+            pattern: Some("Cvccvc-9999-!!\\9".parse()?),
+            symbols: Some("#%".into()),
+            exclude: "0".into(),
+            ..Default::default()
+        };
+        let mut rng = make_rng(RngKind::Chacha, Some(5))?;
+        for _ in 0..100 {
+            let password: Vec<char> = generate_with_rng(&policy, &mut rng)?.chars().collect();
+            assert_eq!(password.len(), 15);
+            assert!(CONSONANTS.to_uppercase().contains(password[0]));
+            assert!(VOWELS.contains(password[1]));
+            assert!(password[7..11]
+                .iter()
+                .all(|c| c.is_ascii_digit() && *c != '0'));
+            assert!(password[12..14].iter().all(|c| "#%".contains(*c)));
+            assert_eq!((password[6], password[11], password[14]), ('-', '-', '9'));
+        }
+        // 21 * 5 * 21 * 21 * 5 * 21 * 9^4 * 2^2
+        let expected = 4.0 * 21f64.log2() + 2.0 * 5f64.log2() + 4.0 * 9f64.log2() + 2.0;
+        assert!((password_entropy(&policy)? - expected).abs() < 1e-9);
+
+        for pattern in ["", "-\\9", "abc\\"] {
+            let ret = pattern.parse::<PasswordPattern>().and_then(|p| {
+                password_entropy(&PasswordPolicy {
+                    pattern: Some(p),
+                    ..Default::default()
+                })
+            });
+            assert!(ret.is_err(), "{:?}", pattern);
+        }
+        let exclude_all = PasswordPolicy {
+            pattern: Some("v".parse()?),
+            exclude: VOWELS.into(),
+            ..Default::default()
+        };
+        assert!(generate_with_policy(&exclude_all).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_pronounceable() -> Result<()> {
+        let policy = PasswordPolicy {
+            length: 9,
+            pattern: Some(PasswordPattern::pronounceable(9)),
+            ..Default::default()
+        };
+        let password = generate_with_policy(&policy)?;
+        for (i, c) in password.chars().enumerate() {
+            assert_eq!(VOWELS.contains(c), i % 2 == 1, "{}", password);
+        }
+        // far less than the 9 * log2(70) bits of a random password
+        let entropy = password_entropy(&policy)?;
+        assert!((entropy - (5.0 * 21f64.log2() + 4.0 * 5f64.log2())).abs() < 1e-9);
+        assert!(
+            entropy
+                < password_entropy(&PasswordPolicy {
+                    length: 9,
+                    ..Default::default()
+                })?
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,275 @@
+use std::io::{BufRead, BufReader, Read, Write};
+
+use crate::cli::OutputFormat;
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use zxcvbn::{matching::patterns::MatchPattern, matching::Match, zxcvbn};
+
+/// The zxcvbn estimate of one candidate. Candidates are referred to by their
+/// line so reports can go to CI logs without the passwords, though matched
+/// patterns still show the weak parts.
+#[derive(Debug, Clone, Serialize)]
+pub struct StrengthReport {
+    pub line: usize,
+    // from 0 to 4, below 3 is too weak
+    pub score: u8,
+    pub guesses_log10: f64,
+    pub crack_times: CrackTimes,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+    pub suggestions: Vec<String>,
+    pub patterns: Vec<PatternMatch>,
+}
+
+/// Time to guess the password in the attack scenarios of zxcvbn.
+#[derive(Debug, Clone, Serialize)]
+pub struct CrackTimes {
+    // 100 guesses per hour
+    pub online_throttling: String,
+    // 10 guesses per second
+    pub online_no_throttling: String,
+    // 10k guesses per second, e.g. bcrypt
+    pub offline_slow_hashing: String,
+    // 10B guesses per second, e.g. md5
+    pub offline_fast_hashing: String,
+}
+
+/// A part of the password guessed as a whole, e.g. a dictionary word or a
+/// keyboard walk.
+#[derive(Debug, Clone, Serialize)]
+pub struct PatternMatch {
+    pub pattern: &'static str,
+    // left out for bruteforce, which is the random part of the password
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// Estimates the strength of a password. `user_inputs` are words an attacker
+/// would try first, like the user name or the company.
+pub fn check_password(password: &str, line: usize, user_inputs: &[&str]) -> StrengthReport {
+    let estimate = zxcvbn(password, user_inputs);
+    let times = estimate.crack_times();
+    let (warning, suggestions) = match estimate.feedback() {
+        Some(feedback) => (
+            feedback.warning().map(|w| w.to_string()),
+            feedback
+                .suggestions()
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+        ),
+        None => (None, Vec::new()),
+    };
+    StrengthReport {
+        line,
+        score: estimate.score().into(),
+        guesses_log10: (estimate.guesses_log10() * 100.0).round() / 100.0,
+        crack_times: CrackTimes {
+            online_throttling: times.online_throttling_100_per_hour().to_string(),
+            online_no_throttling: times.online_no_throttling_10_per_second().to_string(),
+            offline_slow_hashing: times.offline_slow_hashing_1e4_per_second().to_string(),
+            offline_fast_hashing: times.offline_fast_hashing_1e10_per_second().to_string(),
+        },
+        warning,
+        suggestions,
+        patterns: estimate.sequence().iter().map(pattern_match).collect(),
+    }
+}
+
+/// Checks every line of the input as a candidate, blank lines are skipped.
+pub fn check_passwords(reader: impl Read, user_inputs: &[&str]) -> Result<Vec<StrengthReport>> {
+    let mut reports = Vec::new();
+    for (i, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line?;
+        let password = line.strip_suffix('\r').unwrap_or(&line);
+        if !password.is_empty() {
+            reports.push(check_password(password, i + 1, user_inputs));
+        }
+    }
+    Ok(reports)
+}
+
+/// Writes the reports as text for the table format, or as json, ndjson or
+/// yaml.
+pub fn write_strength_reports(
+    reports: &[StrengthReport],
+    mut writer: impl Write,
+    format: OutputFormat,
+) -> Result<()> {
+    match format {
+        OutputFormat::Table => {
+            for report in reports {
+                write_text(report, &mut writer)?;
+            }
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, reports)?;
+            writeln!(writer)?;
+        }
+        OutputFormat::Ndjson => {
+            for report in reports {
+                serde_json::to_writer(&mut writer, report)?;
+                writeln!(writer)?;
+            }
+        }
+        OutputFormat::Yaml => serde_yaml::to_writer(&mut writer, reports)?,
+        format => {
+            return Err(anyhow!(
+                "Strength reports can't be written as {}",
+                Into::<&str>::into(format)
+            ))
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_text(report: &StrengthReport, writer: &mut impl Write) -> Result<()> {
+    writeln!(
+        writer,
+        "line {}: score {}/4, 10^{:.1} guesses",
+        report.line, report.score, report.guesses_log10
+    )?;
+    let times = &report.crack_times;
+    for (scenario, time) in [
+        ("online, throttled", &times.online_throttling),
+        ("online, unthrottled", &times.online_no_throttling),
+        ("offline, slow hash", &times.offline_slow_hashing),
+        ("offline, fast hash", &times.offline_fast_hashing),
+    ] {
+        writeln!(writer, "  {:<21}{}", scenario, time)?;
+    }
+    if let Some(warning) = &report.warning {
+        writeln!(writer, "  warning: {}", warning)?;
+    }
+    for suggestion in &report.suggestions {
+        writeln!(writer, "  suggestion: {}", suggestion)?;
+    }
+    for pattern in &report.patterns {
+        write!(writer, "  pattern: {}", pattern.pattern)?;
+        if let Some(token) = &pattern.token {
+            write!(writer, " {:?}", token)?;
+        }
+        match &pattern.detail {
+            Some(detail) => writeln!(writer, " ({})", detail)?,
+            None => writeln!(writer)?,
+        }
+    }
+    Ok(())
+}
+
+fn pattern_match(m: &Match) -> PatternMatch {
+    let (pattern, detail) = match &m.pattern {
+        MatchPattern::Dictionary(p) => {
+            let mut detail = format!(
+                "{} #{} in {}",
+                p.matched_word,
+                p.rank,
+                snake_case(&format!("{:?}", p.dictionary_name))
+            );
+            if p.reversed {
+                detail.push_str(", reversed");
+            }
+            if p.l33t {
+                detail.push_str(", l33t");
+            }
+            ("dictionary", Some(detail))
+        }
+        MatchPattern::Spatial(p) => (
+            "spatial",
+            Some(format!("{} keyboard, {} turns", p.graph, p.turns)),
+        ),
+        MatchPattern::Repeat(p) => (
+            "repeat",
+            Some(format!("{:?} {} times", p.base_token, p.repeat_count)),
+        ),
+        MatchPattern::Sequence(p) => ("sequence", Some(p.sequence_name.to_string())),
+        MatchPattern::Regex(p) => ("regex", Some(p.regex_name.to_string())),
+        MatchPattern::Date(p) => (
+            "date",
+            Some(format!("{:04}-{:02}-{:02}", p.year, p.month, p.day)),
+        ),
+        MatchPattern::BruteForce => ("bruteforce", None),
+    };
+    PatternMatch {
+        pattern,
+        token: detail.as_ref().map(|_| m.token.clone()),
+        detail,
+    }
+}
+
+// the dictionary names of zxcvbn are only exposed through Debug, e.g. `UsTvAndFilm`
+fn snake_case(name: &str) -> String {
+    let mut ret = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            ret.push('_');
+        }
+        ret.extend(c.to_lowercase());
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_weak_and_strong_passwords() -> Result<()> {
+        let input = "password\r\n\nqwerty1990\nt7#Kq!v9Lz@2mW$x\n";
+        let reports = check_passwords(input.as_bytes(), &[])?;
+        assert_eq!(
+            reports.iter().map(|r| r.line).collect::<Vec<_>>(),
+            [1, 3, 4]
+        );
+
+        let weak = &reports[0];
+        assert_eq!(weak.score, 0);
+        assert!(weak.warning.is_some());
+        assert_eq!(weak.patterns[0].pattern, "dictionary");
+        assert_eq!(weak.patterns[0].token.as_deref(), Some("password"));
+        assert_eq!(weak.crack_times.offline_fast_hashing, "less than a second");
+
+        let patterns: Vec<_> = reports[1].patterns.iter().map(|p| p.pattern).collect();
+        assert!(patterns.contains(&"spatial") || patterns.contains(&"dictionary"));
+        assert!(reports[2].score >= 3);
+        assert!(reports[2].suggestions.is_empty());
+        assert!(reports[2]
+            .patterns
+            .iter()
+            .all(|p| p.pattern != "bruteforce" || p.token.is_none()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_inputs_weaken_passwords() {
+        let without = check_password("acmecorp2024", 1, &[]);
+        let with = check_password("acmecorp2024", 1, &["acmecorp"]);
+        assert!(with.guesses_log10 < without.guesses_log10);
+        assert!(with.patterns[0]
+            .detail
+            .as_deref()
+            .unwrap()
+            .ends_with("in user_inputs"));
+    }
+
+    #[test]
+    fn test_write_strength_reports() -> Result<()> {
+        let reports = [check_password("password", 1, &[])];
+        let mut buf = Vec::new();
+        write_strength_reports(&reports, &mut buf, OutputFormat::Table)?;
+        let text = String::from_utf8(buf)?;
+        assert!(text.starts_with("line 1: score 0/4"));
+        assert!(text.contains("  pattern: dictionary \"password\" (password #2 in passwords)"));
+
+        let mut buf = Vec::new();
+        write_strength_reports(&reports, &mut buf, OutputFormat::Json)?;
+        let json: serde_json::Value = serde_json::from_slice(&buf)?;
+        assert_eq!(json[0]["score"], 0);
+        assert!(json[0].get("password").is_none());
+        assert!(write_strength_reports(&reports, Vec::new(), OutputFormat::Csv).is_err());
+        Ok(())
+    }
+}
@@ -1,11 +1,60 @@
 mod b64;
 mod csv_convert;
+mod csv_diff;
+mod csv_encoding;
+mod csv_filter;
+mod csv_inspect;
+mod csv_join;
+mod csv_mask;
+mod csv_output;
+mod csv_parquet;
+mod csv_query;
+mod csv_reader;
+mod csv_schema;
+mod csv_sort;
+mod csv_split;
+mod csv_stats;
+mod csv_xlsx;
 mod genpass;
+mod genpass_check;
 mod http_serve;
 mod text;
 
 pub use b64::*;
-pub use csv_convert::process_csv;
-pub use genpass::generate_password;
+pub use csv_convert::{
+    convert_csv, flatten_object, process_csv, process_to_csv, records_to_csv, unflatten_object,
+    CsvConvertOptions,
+};
+pub use csv_diff::{diff_csv, write_diff, ColumnChange, CsvDiff, DiffStyle, RowChange};
+pub use csv_encoding::{decode_reader, InputEncoding};
+pub use csv_filter::{Projection, RecordFilter};
+pub use csv_inspect::{
+    inspect_csv, validate_csv, write_report, ColumnReport, CsvReport, MalformedRow,
+    ValidationReport,
+};
+pub use csv_join::{join_csv, JoinKey, JoinKind};
+pub use csv_mask::{mask_csv, ColumnMask, MaskRule};
+pub use csv_output::{record_writer, RecordWriter};
+pub use csv_parquet::ParquetWriter;
+pub use csv_query::{CsvTable, SqlQuery};
+pub use csv_reader::{CsvReadOptions, CsvRecords};
+pub use csv_schema::{
+    coerce_value, infer_type, infer_value, widen_type, CoercionError, ColumnSpec, ColumnType,
+    CsvSchema, RecordTyper,
+};
+pub use csv_sort::{dedup_csv, sort_csv, SortOptions};
+pub use csv_split::{merge_csv, split_csv, SplitBy};
+pub use csv_stats::{csv_stats, Aggregate, AggregateFn};
+pub use csv_xlsx::{is_spreadsheet, read_sheet};
+pub use genpass::{
+    default_wordlist, generate_passphrase, generate_password, generate_with_policy,
+    generate_with_rng, load_wordlist, make_rng, passphrase_entropy, password_entropy,
+    uniform_index, write_secrets, Charset, GeneratedSecret, PassphraseOptions, PasswordPattern,
+    PasswordPolicy, RngKind, SecretFormat,
+};
+pub use genpass_check::{
+    check_password, check_passwords, write_strength_reports, CrackTimes, PatternMatch,
+    StrengthReport,
+};
 pub use http_serve::*;
 pub use text::*;
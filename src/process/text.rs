@@ -28,6 +28,18 @@ impl Blake3 {
         let key = (&key[..32]).try_into()?;
         Ok(Self::new(key))
     }
+
+    pub fn hash(&self, data: &[u8]) -> blake3::Hash {
+        blake3::keyed_hash(&self.key, data)
+    }
+
+    // keyed hash output of any length, e.g. to derive fake values from
+    pub fn hash_xof(&self, data: &[u8]) -> blake3::OutputReader {
+        blake3::Hasher::new_keyed(&self.key)
+            .update(data)
+            .finalize_xof()
+    }
+
     pub fn generate() -> Result<HashMap<&'static str, Vec<u8>>> {
         // generate a random 32-byte key
         let key = generate_password(32, true, true, true, true)?;
@@ -41,7 +53,7 @@ impl TextSigner for Blake3 {
     fn sign(&self, reader: &mut dyn Read) -> Result<Vec<u8>> {
         let mut buf = Vec::new();
         reader.read_to_end(&mut buf)?;
-        let signature = blake3::keyed_hash(&self.key, &buf);
+        let signature = self.hash(&buf);
         println!("sign trait signature: {:?}", signature);
         Ok(signature.as_bytes().to_vec())
     }
@@ -52,7 +64,7 @@ impl TextVerify for Blake3 {
         let mut buf = Vec::new();
         let cnt = reader.read_to_end(&mut buf)?;
         println!("cnt: {}", cnt);
-        let ret = blake3::keyed_hash(&self.key, &buf);
+        let ret = self.hash(&buf);
         for (i, (sig_byte, ret_byte)) in signature.iter().zip(ret.as_bytes()).enumerate() {
             if sig_byte != ret_byte {
                 println!(
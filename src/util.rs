@@ -1,4 +1,13 @@
-use std::{fs::File, io::Read};
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, Read, Write},
+};
+
+#[cfg(unix)]
+use std::{
+    fs::Permissions,
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+};
 
 pub fn get_content(key: &str) -> anyhow::Result<Vec<u8>> {
     let mut reader = get_reader(key)?;
@@ -15,3 +24,28 @@ pub fn get_reader(input: &str) -> anyhow::Result<Box<dyn Read>> {
     };
     Ok(reader)
 }
+
+pub fn get_writer(output: &str) -> anyhow::Result<Box<dyn Write>> {
+    let writer: Box<dyn Write> = if output == "-" {
+        Box::new(BufWriter::new(std::io::stdout().lock()))
+    } else {
+        Box::new(BufWriter::new(File::create(output)?))
+    };
+    Ok(writer)
+}
+
+// like get_writer, but files are only readable by their owner as they hold secrets
+pub fn get_secret_writer(output: &str) -> anyhow::Result<Box<dyn Write>> {
+    if output == "-" {
+        return get_writer(output);
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    OpenOptionsExt::mode(&mut options, 0o600);
+    let file = options.open(output)?;
+    // the mode only applies to new files
+    #[cfg(unix)]
+    file.set_permissions(Permissions::from_mode(0o600))?;
+    Ok(Box::new(BufWriter::new(file)))
+}
@@ -0,0 +1,118 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    io::{self, Read, Write},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use rcli::{convert_csv, OutputFormat};
+
+// tracks the live and peak heap usage of the test binary
+struct PeakAlloc;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for PeakAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let current = CURRENT.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+            PEAK.fetch_max(current, Ordering::SeqCst);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        CURRENT.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+}
+
+#[global_allocator]
+static GLOBAL: PeakAlloc = PeakAlloc;
+
+// produces a csv file on the fly, so the input itself is never in memory
+struct GeneratedCsv {
+    rows: usize,
+    next: usize,
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl GeneratedCsv {
+    fn new(rows: usize) -> Self {
+        Self {
+            rows,
+            next: 0,
+            line: b"id,name,position,nationality,score\n".to_vec(),
+            pos: 0,
+        }
+    }
+}
+
+impl Read for GeneratedCsv {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.line.len() {
+            if self.next == self.rows {
+                return Ok(0);
+            }
+            self.line.clear();
+            writeln!(
+                self.line,
+                "{},Player {},Midfielder,\"Italy, EU\",{}.5",
+                self.next,
+                self.next,
+                self.next % 100
+            )?;
+            self.pos = 0;
+            self.next += 1;
+        }
+        let n = buf.len().min(self.line.len() - self.pos);
+        buf[..n].copy_from_slice(&self.line[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+// discards the output, only counting the bytes written
+#[derive(Default)]
+struct CountingSink {
+    bytes: usize,
+}
+
+impl Write for CountingSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.bytes += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn convert_large_generated_csv_in_constant_memory() -> anyhow::Result<()> {
+    const ROWS: usize = 100_000;
+    const LIMIT: usize = 1024 * 1024;
+
+    for format in [OutputFormat::Json, OutputFormat::Yaml] {
+        let baseline = CURRENT.load(Ordering::SeqCst);
+        PEAK.store(baseline, Ordering::SeqCst);
+
+        let mut sink = CountingSink::default();
+        let count = convert_csv(
+            GeneratedCsv::new(ROWS),
+            &mut sink,
+            format,
+            &Default::default(),
+        )?;
+        let peak = PEAK.load(Ordering::SeqCst) - baseline;
+
+        assert_eq!(count, ROWS);
+        // the output alone is several times the limit
+        assert!(sink.bytes > 8 * LIMIT);
+        assert!(peak < LIMIT, "peak heap usage {} exceeds {}", peak, LIMIT);
+    }
+    Ok(())
+}